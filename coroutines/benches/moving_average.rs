@@ -0,0 +1,97 @@
+#![feature(test)]
+#![feature(coroutine_trait)]
+
+extern crate test;
+
+use coroutines::{
+    CumulativeAverage, ExponentialMovingAverage, MovingAverage, WeightedMovingAverage,
+    moving_average_coroutine,
+};
+use std::ops::{Coroutine, CoroutineState};
+use std::pin::Pin;
+use test::{Bencher, black_box};
+
+const SAMPLES: usize = 2_000_000;
+
+fn samples() -> Vec<f64> {
+    // cheap deterministic noise around a slowly moving level
+    (0..SAMPLES)
+        .map(|i| (i / 1000) as f64 + ((i * 7919) % 1000) as f64 / 1000.0)
+        .collect()
+}
+
+// the previous implementation, shifting the whole window on every sample
+fn vec_remove_average(data: &[f64], window_size: usize) -> f64 {
+    let mut window: Vec<f64> = Vec::with_capacity(window_size);
+    let mut sum = 0.0;
+    let mut average = 0.0;
+    for &value in data {
+        sum += value;
+        window.push(value);
+        if window.len() > window_size {
+            sum -= window.remove(0);
+        }
+        average = sum / window.len() as f64;
+    }
+    average
+}
+
+fn run(data: &[f64], mut push: impl FnMut(f64) -> f64) -> f64 {
+    data.iter().fold(0.0, |_, &value| push(value))
+}
+
+#[bench]
+fn vec_remove_window_1024(b: &mut Bencher) {
+    let data = samples();
+    b.iter(|| vec_remove_average(black_box(&data), 1024));
+}
+
+#[bench]
+fn ring_buffer_window_1024(b: &mut Bencher) {
+    let data = samples();
+    b.iter(|| {
+        let mut average = MovingAverage::new(1024);
+        run(black_box(&data), |v| average.push(v))
+    });
+}
+
+#[bench]
+fn ring_buffer_window_1024_as_coroutine(b: &mut Bencher) {
+    let data = samples();
+    b.iter(|| {
+        let mut average = moving_average_coroutine(1024);
+        run(black_box(&data), |v| {
+            match Pin::new(&mut average).resume(v) {
+                CoroutineState::Yielded(avg) => avg,
+                CoroutineState::Complete(()) => unreachable!(),
+            }
+        })
+    });
+}
+
+#[bench]
+fn ewma(b: &mut Bencher) {
+    let data = samples();
+    b.iter(|| {
+        let mut average = ExponentialMovingAverage::with_span(1024);
+        run(black_box(&data), |v| average.push(v))
+    });
+}
+
+#[bench]
+fn weighted_window_1024(b: &mut Bencher) {
+    let data = samples();
+    b.iter(|| {
+        let mut average = WeightedMovingAverage::new(1024);
+        run(black_box(&data), |v| average.push(v))
+    });
+}
+
+#[bench]
+fn cumulative(b: &mut Bencher) {
+    let data = samples();
+    b.iter(|| {
+        let mut average = CumulativeAverage::new();
+        run(black_box(&data), |v| average.push(v))
+    });
+}
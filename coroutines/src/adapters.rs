@@ -0,0 +1,364 @@
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+use std::ops::{Coroutine, CoroutineState};
+use std::pin::Pin;
+
+// all adapters resume the wrapped coroutines through `Pin::new`, so they need them to be `Unpin`;
+// a coroutine that is not can be wrapped in `Box::pin` first
+
+/// Iterator over the yielded values, keeps the return value once the coroutine completes.
+pub struct CoroutineIter<C: Coroutine<()>> {
+    coroutine: C,
+    returned: Option<C::Return>,
+    complete: bool,
+}
+
+impl<C: Coroutine<()> + Unpin> CoroutineIter<C> {
+    pub fn new(coroutine: C) -> Self {
+        CoroutineIter {
+            coroutine,
+            returned: None,
+            complete: false,
+        }
+    }
+
+    /// `None` until the iterator has returned `None`
+    pub fn return_value(&self) -> Option<&C::Return> {
+        self.returned.as_ref()
+    }
+
+    pub fn into_return_value(self) -> Option<C::Return> {
+        self.returned
+    }
+}
+
+impl<C: Coroutine<()> + Unpin> Iterator for CoroutineIter<C> {
+    type Item = C::Yield;
+
+    fn next(&mut self) -> Option<C::Yield> {
+        // resuming a completed coroutine panics, so remember that it is done
+        if self.complete {
+            return None;
+        }
+        match Pin::new(&mut self.coroutine).resume(()) {
+            CoroutineState::Yielded(value) => Some(value),
+            CoroutineState::Complete(result) => {
+                self.returned = Some(result);
+                self.complete = true;
+                None
+            }
+        }
+    }
+}
+
+/// Combinators working on the coroutines directly, for any resume argument where possible.
+pub trait CoroutineExt<R>: Coroutine<R> + Sized + Unpin {
+    fn iter(self) -> CoroutineIter<Self>
+    where
+        Self: Coroutine<()>,
+    {
+        CoroutineIter::new(self)
+    }
+
+    fn map_yield<F, U>(self, f: F) -> MapYield<Self, F>
+    where
+        F: FnMut(Self::Yield) -> U,
+    {
+        MapYield { inner: self, f }
+    }
+
+    /// skipped values are not seen by the caller, so the coroutine is resumed again
+    /// right away, which only makes sense without resume arguments
+    fn filter_yield<P>(self, predicate: P) -> FilterYield<Self, P>
+    where
+        Self: Coroutine<()>,
+        P: FnMut(&<Self as Coroutine<()>>::Yield) -> bool,
+    {
+        FilterYield {
+            inner: self,
+            predicate,
+        }
+    }
+
+    /// completes with `None` after `n` values, or with `Some(return value)` if the inner one finished first
+    fn take(self, n: usize) -> Take<Self> {
+        Take {
+            inner: self,
+            remaining: n,
+            done: false,
+        }
+    }
+
+    /// runs `next` after `self` completes, the resume argument that completed `self`
+    /// is also passed to `next`
+    fn chain<N>(self, next: N) -> Chain<Self, N, <Self as Coroutine<R>>::Return>
+    where
+        N: Coroutine<R, Yield = Self::Yield> + Unpin,
+        R: Clone,
+    {
+        Chain {
+            first: self,
+            second: next,
+            state: ChainState::First,
+        }
+    }
+
+    /// yields pairs until either completes, both get a copy of each resume argument
+    fn zip<O>(self, other: O) -> Zip<Self, O>
+    where
+        O: Coroutine<R> + Unpin,
+        R: Clone,
+    {
+        Zip {
+            first: self,
+            second: other,
+        }
+    }
+}
+
+impl<R, C: Coroutine<R> + Unpin> CoroutineExt<R> for C {}
+
+pub struct MapYield<C, F> {
+    inner: C,
+    f: F,
+}
+
+impl<R, C, F, U> Coroutine<R> for MapYield<C, F>
+where
+    C: Coroutine<R> + Unpin,
+    F: FnMut(C::Yield) -> U + Unpin,
+{
+    type Yield = U;
+    type Return = C::Return;
+
+    fn resume(self: Pin<&mut Self>, arg: R) -> CoroutineState<U, C::Return> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).resume(arg) {
+            CoroutineState::Yielded(value) => CoroutineState::Yielded((this.f)(value)),
+            CoroutineState::Complete(result) => CoroutineState::Complete(result),
+        }
+    }
+}
+
+pub struct FilterYield<C, P> {
+    inner: C,
+    predicate: P,
+}
+
+impl<C, P> Coroutine<()> for FilterYield<C, P>
+where
+    C: Coroutine<()> + Unpin,
+    P: FnMut(&C::Yield) -> bool + Unpin,
+{
+    type Yield = C::Yield;
+    type Return = C::Return;
+
+    fn resume(self: Pin<&mut Self>, _arg: ()) -> CoroutineState<C::Yield, C::Return> {
+        let this = self.get_mut();
+        loop {
+            match Pin::new(&mut this.inner).resume(()) {
+                CoroutineState::Yielded(value) if !(this.predicate)(&value) => continue,
+                state => return state,
+            }
+        }
+    }
+}
+
+pub struct Take<C> {
+    inner: C,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R, C: Coroutine<R> + Unpin> Coroutine<R> for Take<C> {
+    type Yield = C::Yield;
+    type Return = Option<C::Return>;
+
+    fn resume(self: Pin<&mut Self>, arg: R) -> CoroutineState<C::Yield, Option<C::Return>> {
+        let this = self.get_mut();
+        assert!(!this.done, "`Take` resumed after completion");
+        if this.remaining == 0 {
+            this.done = true;
+            return CoroutineState::Complete(None);
+        }
+        match Pin::new(&mut this.inner).resume(arg) {
+            CoroutineState::Yielded(value) => {
+                this.remaining -= 1;
+                CoroutineState::Yielded(value)
+            }
+            CoroutineState::Complete(result) => {
+                this.done = true;
+                CoroutineState::Complete(Some(result))
+            }
+        }
+    }
+}
+
+enum ChainState<A> {
+    First,
+    Second(A),
+    Done,
+}
+
+pub struct Chain<A, B, AR> {
+    first: A,
+    second: B,
+    state: ChainState<AR>,
+}
+
+impl<R, A, B> Coroutine<R> for Chain<A, B, A::Return>
+where
+    R: Clone,
+    A: Coroutine<R> + Unpin,
+    B: Coroutine<R, Yield = A::Yield> + Unpin,
+    A::Return: Unpin,
+{
+    type Yield = A::Yield;
+    type Return = (A::Return, B::Return);
+
+    fn resume(self: Pin<&mut Self>, arg: R) -> CoroutineState<A::Yield, Self::Return> {
+        let this = self.get_mut();
+        if let ChainState::First = this.state {
+            match Pin::new(&mut this.first).resume(arg.clone()) {
+                CoroutineState::Yielded(value) => return CoroutineState::Yielded(value),
+                CoroutineState::Complete(result) => this.state = ChainState::Second(result),
+            }
+        }
+        match Pin::new(&mut this.second).resume(arg) {
+            CoroutineState::Yielded(value) => CoroutineState::Yielded(value),
+            CoroutineState::Complete(second) => {
+                match std::mem::replace(&mut this.state, ChainState::Done) {
+                    ChainState::Second(first) => CoroutineState::Complete((first, second)),
+                    _ => panic!("`Chain` resumed after completion"),
+                }
+            }
+        }
+    }
+}
+
+/// Which side of a [`Zip`] completed first, with its return value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZipEnd<A, B> {
+    First(A),
+    Second(B),
+}
+
+pub struct Zip<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<R, A, B> Coroutine<R> for Zip<A, B>
+where
+    R: Clone,
+    A: Coroutine<R> + Unpin,
+    B: Coroutine<R> + Unpin,
+{
+    type Yield = (A::Yield, B::Yield);
+    type Return = ZipEnd<A::Return, B::Return>;
+
+    // the second one is not resumed once the first completes, its state stays untouched
+    fn resume(self: Pin<&mut Self>, arg: R) -> CoroutineState<Self::Yield, Self::Return> {
+        let this = self.get_mut();
+        let a = match Pin::new(&mut this.first).resume(arg.clone()) {
+            CoroutineState::Yielded(a) => a,
+            CoroutineState::Complete(result) => {
+                return CoroutineState::Complete(ZipEnd::First(result));
+            }
+        };
+        match Pin::new(&mut this.second).resume(arg) {
+            CoroutineState::Yielded(b) => CoroutineState::Yielded((a, b)),
+            CoroutineState::Complete(result) => CoroutineState::Complete(ZipEnd::Second(result)),
+        }
+    }
+}
+
+// both sides one after the other, so a zipped pair of stages can be saved inside a pipeline
+impl<A: Checkpoint, B: Checkpoint> Checkpoint for Zip<A, B> {
+    fn encode(&self, out: &mut Encoder) {
+        self.first.encode(out);
+        self.second.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        Ok(Zip {
+            first: A::decode(input)?,
+            second: B::decode(input)?,
+        })
+    }
+}
+
+#[cfg(test)]
+use crate::{fibonacci_coroutine, moving_average_coroutine};
+
+#[test]
+fn test_iterator_adapter_keeps_return_value() {
+    let mut fib = fibonacci_coroutine(6).iter();
+    assert_eq!(fib.return_value(), None);
+    assert_eq!(fib.by_ref().collect::<Vec<_>>(), vec![0, 1, 1, 2, 3, 5]);
+    assert_eq!(fib.next(), None);
+    assert_eq!(fib.into_return_value(), Some("Fibonacci sequence complete"));
+}
+
+#[test]
+fn test_combinators() {
+    let even_squares: Vec<u64> = fibonacci_coroutine(10)
+        .filter_yield(|n| n % 2 == 0)
+        .map_yield(|n| n * n)
+        .iter()
+        .collect();
+    assert_eq!(even_squares, vec![0, 4, 64, 1156]);
+
+    let mut cut = fibonacci_coroutine(10).take(3).iter();
+    assert_eq!(cut.by_ref().collect::<Vec<_>>(), vec![0, 1, 1]);
+    assert_eq!(cut.into_return_value(), Some(None));
+    let mut short = fibonacci_coroutine(2).take(3).iter();
+    assert_eq!(short.by_ref().count(), 2);
+    assert_eq!(
+        short.into_return_value(),
+        Some(Some("Fibonacci sequence complete"))
+    );
+
+    let mut chained = fibonacci_coroutine(3).chain(fibonacci_coroutine(2)).iter();
+    assert_eq!(chained.by_ref().collect::<Vec<_>>(), vec![0, 1, 1, 0, 1]);
+    assert!(chained.return_value().is_some());
+
+    let mut zipped = fibonacci_coroutine(5)
+        .zip(fibonacci_coroutine(3).map_yield(|n| n * 10))
+        .iter();
+    assert_eq!(
+        zipped.by_ref().collect::<Vec<_>>(),
+        vec![(0, 0), (1, 10), (1, 10)]
+    );
+    assert_eq!(
+        zipped.into_return_value(),
+        Some(ZipEnd::Second("Fibonacci sequence complete"))
+    );
+}
+
+#[test]
+fn test_combinators_with_resume_arguments() {
+    // a moving average never completes on its own, `take` bounds it
+    let mut rounded = moving_average_coroutine(2)
+        .map_yield(|avg| avg.round() as i64)
+        .take(2);
+    assert!(matches!(
+        Pin::new(&mut rounded).resume(1.0),
+        CoroutineState::Yielded(1)
+    ));
+    assert!(matches!(
+        Pin::new(&mut rounded).resume(4.0),
+        CoroutineState::Yielded(3)
+    ));
+    assert!(matches!(
+        Pin::new(&mut rounded).resume(9.0),
+        CoroutineState::Complete(None)
+    ));
+
+    // both averages see every value
+    let mut both = moving_average_coroutine(1).zip(moving_average_coroutine(3));
+    Pin::new(&mut both).resume(3.0);
+    let CoroutineState::Yielded((last, avg)) = Pin::new(&mut both).resume(6.0) else {
+        panic!("moving averages never complete");
+    };
+    assert_eq!((last, avg), (6.0, 4.5));
+}
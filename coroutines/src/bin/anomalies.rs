@@ -0,0 +1,388 @@
+#![feature(coroutine_trait)]
+
+use coroutines::{
+    AnnotatedRow, AnnotatedWriter, Column, CompensatedSum, CoroutineExt, CsvOptions, CusumParams,
+    Direction, InputFormat, OutlierReport, OutputFormat, Pipeline, SeriesReader, ThresholdDetector,
+    cusum_detector_coroutine, iqr_detector_coroutine, mad_detector_coroutine,
+    moving_average_coroutine, zscore_detector_coroutine,
+};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::{Coroutine, CoroutineState};
+use std::pin::Pin;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Flags outliers in a series of numbers, one per line or a CSV column.
+
+Usage: anomalies [OPTIONS] [FILE]
+
+Reads FILE, or stdin when it is missing or `-`.
+
+Options:
+  -d, --detector NAME   absolute, zscore, mad, iqr or cusum [default: zscore]
+  -w, --window N        window size, CUSUM warmup [default: 20]
+  -t, --threshold X     absolute: distance from the moving average [default: 5]
+                        zscore: standard deviations [default: 3]
+                        mad: modified z-score [default: 3.5]
+                        iqr: interquartile ranges beyond the quartiles [default: 1.5]
+                        cusum: accumulated standard deviations [default: 5]
+      --csv             input is CSV with a header line
+  -c, --column COLUMN   CSV column name or 0-based index [default: 0]
+      --delimiter C     CSV delimiter [default: ,]
+  -o, --output FILE     also write every value annotated with its baseline and verdict,
+                        as NDJSON if FILE ends in .ndjson or .jsonl, CSV otherwise
+  -h, --help            print this help
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Detector {
+    Absolute,
+    ZScore,
+    Mad,
+    Iqr,
+    Cusum,
+}
+
+impl Detector {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "absolute" => Ok(Detector::Absolute),
+            "zscore" => Ok(Detector::ZScore),
+            "mad" => Ok(Detector::Mad),
+            "iqr" => Ok(Detector::Iqr),
+            "cusum" => Ok(Detector::Cusum),
+            other => Err(format!("unknown detector `{other}`")),
+        }
+    }
+
+    fn default_threshold(self) -> f64 {
+        match self {
+            Detector::Absolute | Detector::Cusum => 5.0,
+            Detector::ZScore => 3.0,
+            Detector::Mad => 3.5,
+            Detector::Iqr => 1.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Args {
+    input: Option<String>,
+    detector: Detector,
+    window: usize,
+    threshold: f64,
+    csv: Option<CsvOptions>,
+    output: Option<String>,
+}
+
+// `None` when help was asked for
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut input = None;
+    let mut detector = Detector::ZScore;
+    let mut window = 20;
+    let mut threshold = None;
+    let mut csv = false;
+    let mut column = Column::Index(0);
+    let mut delimiter = ',';
+    let mut output = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // `--flag=value` is accepted as well as `--flag value`
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-d" | "--detector" => detector = Detector::parse(&value(&flag)?)?,
+            "-w" | "--window" => {
+                window = value(&flag)?
+                    .parse()
+                    .ok()
+                    .filter(|&w| w > 0)
+                    .ok_or("window must be a positive integer")?;
+            }
+            "-t" | "--threshold" => {
+                let raw = value(&flag)?;
+                threshold = Some(
+                    raw.parse::<f64>()
+                        .ok()
+                        .filter(|t| t.is_finite() && *t >= 0.0)
+                        .ok_or_else(|| format!("invalid threshold `{raw}`"))?,
+                );
+            }
+            "--csv" => csv = true,
+            "-c" | "--column" => {
+                let raw = value(&flag)?;
+                column = match raw.parse() {
+                    Ok(index) => Column::Index(index),
+                    Err(_) => Column::Name(raw),
+                };
+            }
+            "--delimiter" => {
+                let raw = value(&flag)?;
+                let mut chars = raw.chars();
+                delimiter = match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(format!("delimiter must be a single character, got `{raw}`")),
+                };
+            }
+            "-o" | "--output" => output = Some(value(&flag)?),
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("unknown option `{flag}`"));
+            }
+            _ if input.is_some() => return Err(format!("unexpected argument `{arg}`")),
+            _ => input = Some(arg).filter(|path| path != "-"),
+        }
+    }
+    if detector == Detector::Cusum && window < 2 {
+        return Err("CUSUM needs a window (warmup) of at least 2".into());
+    }
+
+    Ok(Some(Args {
+        input,
+        detector,
+        window,
+        threshold: threshold.unwrap_or(detector.default_threshold()),
+        csv: csv.then_some(CsvOptions {
+            delimiter,
+            has_header: true,
+            value_column: column,
+            ..CsvOptions::default()
+        }),
+        output,
+    }))
+}
+
+type Stage = Box<dyn Coroutine<f64, Yield = OutlierReport, Return = ()> + Unpin>;
+
+fn detector_stage(args: &Args) -> Stage {
+    let (window, threshold) = (args.window, args.threshold);
+    match args.detector {
+        // the rule is `ThresholdDetector`'s, the average is kept for the report
+        Detector::Absolute => {
+            let mut detector = ThresholdDetector::new(threshold);
+            Box::new(
+                Pipeline::new(moving_average_coroutine(window))
+                    .with_input()
+                    .map_yield(move |(value, average): (f64, f64)| {
+                        let deviation = value - average;
+                        OutlierReport {
+                            value,
+                            baseline: average,
+                            score: deviation.abs(),
+                            direction: if deviation >= 0.0 {
+                                Direction::Above
+                            } else {
+                                Direction::Below
+                            },
+                            is_outlier: detector.push((value, average)).is_some(),
+                        }
+                    }),
+            )
+        }
+        Detector::ZScore => Box::new(zscore_detector_coroutine(window, threshold)),
+        Detector::Mad => Box::new(mad_detector_coroutine(window, threshold)),
+        Detector::Iqr => Box::new(iqr_detector_coroutine(window, threshold)),
+        Detector::Cusum => Box::new(cusum_detector_coroutine(CusumParams {
+            warmup: window,
+            threshold,
+            ..CusumParams::default()
+        })),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    count: u64,
+    outliers: u64,
+    sum: CompensatedSum,
+    min: f64,
+    max: f64,
+}
+
+impl Summary {
+    fn add(&mut self, report: &OutlierReport) {
+        if self.count == 0 {
+            (self.min, self.max) = (report.value, report.value);
+        }
+        self.count += 1;
+        self.outliers += u64::from(report.is_outlier);
+        self.sum.add(report.value);
+        self.min = self.min.min(report.value);
+        self.max = self.max.max(report.value);
+    }
+}
+
+fn run(args: &Args, input: impl BufRead, mut out: impl Write) -> Result<Summary, Box<dyn Error>> {
+    let format = match &args.csv {
+        Some(options) => InputFormat::Csv(options.clone()),
+        None => InputFormat::Lines,
+    };
+    let mut annotated = match &args.output {
+        Some(path) => {
+            let format = if path.ends_with(".ndjson") || path.ends_with(".jsonl") {
+                OutputFormat::Ndjson
+            } else {
+                OutputFormat::Csv
+            };
+            Some(AnnotatedWriter::new(
+                BufWriter::new(File::create(path)?),
+                format,
+            ))
+        }
+        None => None,
+    };
+
+    let mut summary = Summary::default();
+    let mut stage = detector_stage(args);
+    writeln!(
+        out,
+        "{:>8} {:>14} {:>14} {:>10}  direction",
+        "index", "value", "baseline", "score"
+    )?;
+    for (index, value) in SeriesReader::new(input, format).values().enumerate() {
+        let report = match Pin::new(&mut stage).resume(value?) {
+            CoroutineState::Yielded(report) => report,
+            CoroutineState::Complete(()) => unreachable!("detectors never complete"),
+        };
+        summary.add(&report);
+        if let Some(writer) = &mut annotated {
+            writer.write(&AnnotatedRow::from(report))?;
+        }
+        if report.is_outlier {
+            writeln!(
+                out,
+                "{index:>8} {:>14.4} {:>14.4} {:>10.3}  {}",
+                report.value,
+                report.baseline,
+                report.score,
+                match report.direction {
+                    Direction::Above => "above",
+                    Direction::Below => "below",
+                }
+            )?;
+        }
+    }
+    if let Some(writer) = &mut annotated {
+        writer.flush()?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "values:   {}", summary.count)?;
+    writeln!(out, "outliers: {}", summary.outliers)?;
+    if summary.count > 0 {
+        writeln!(
+            out,
+            "mean:     {:.4}",
+            summary.sum.value() / summary.count as f64
+        )?;
+        writeln!(out, "min:      {:.4}", summary.min)?;
+        writeln!(out, "max:      {:.4}", summary.max)?;
+    }
+    Ok(summary)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let stdout = io::stdout().lock();
+    let result = match &args.input {
+        Some(path) => File::open(path)
+            .map_err(|e| format!("cannot open {path}: {e}").into())
+            .and_then(|file| run(&args, BufReader::new(file), stdout)),
+        None => run(&args, io::stdin().lock(), stdout),
+    };
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+fn args(list: &[&str]) -> Result<Option<Args>, String> {
+    parse_args(list.iter().map(|s| s.to_string()))
+}
+
+#[test]
+fn test_parse_args() {
+    let parsed = args(&[
+        "--detector=mad",
+        "-w",
+        "50",
+        "data.csv",
+        "--csv",
+        "-c",
+        "temp",
+    ])
+    .unwrap()
+    .unwrap();
+    assert_eq!(parsed.detector, Detector::Mad);
+    assert_eq!((parsed.window, parsed.threshold), (50, 3.5));
+    assert_eq!(parsed.input.as_deref(), Some("data.csv"));
+    assert_eq!(
+        parsed.csv.unwrap().value_column,
+        Column::Name("temp".into())
+    );
+
+    let defaults = args(&["-", "-t", "2.5"]).unwrap().unwrap();
+    assert_eq!(
+        (defaults.input, defaults.threshold, defaults.csv),
+        (None, 2.5, None)
+    );
+
+    assert_eq!(args(&["-h"]), Ok(None));
+    assert_eq!(args(&["-d", "fft"]), Err("unknown detector `fft`".into()));
+    assert_eq!(
+        args(&["-w", "0"]),
+        Err("window must be a positive integer".into())
+    );
+    assert_eq!(args(&["--window"]), Err("--window needs a value".into()));
+    assert_eq!(args(&["a", "b"]), Err("unexpected argument `b`".into()));
+}
+
+#[test]
+fn test_run_reports_outliers_and_summary() {
+    let parsed = args(&["-d", "absolute", "-w", "3", "-t", "5"])
+        .unwrap()
+        .unwrap();
+    let input = "1\n2\n3\n4\n10\n5\n6\n20\n7\n8\n";
+    let mut out = Vec::new();
+    let summary = run(&parsed, input.as_bytes(), &mut out).unwrap();
+    assert_eq!((summary.count, summary.outliers), (10, 1));
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(
+        lines[1].split_whitespace().collect::<Vec<_>>(),
+        ["7", "20.0000", "10.3333", "9.667", "above"]
+    );
+    assert!(out.contains("mean:     6.6000"));
+
+    let bad = run(&parsed, "1\nx\n".as_bytes(), Vec::new()).unwrap_err();
+    assert_eq!(bad.to_string(), "line 2: expected a number, found `x`");
+}
@@ -0,0 +1,382 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Stage state that can be written to bytes and restored later, e.g. across a restart
+/// of a long running stream job. A restored stage continues with exactly the outputs
+/// the original would have produced.
+///
+/// Every stage writes a 4 byte tag first, so restoring bytes of a different stage fails;
+/// pipelines write their stages one after the other.
+pub trait Checkpoint: Sized {
+    fn encode(&self, out: &mut Encoder);
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError>;
+
+    fn checkpoint(&self) -> Vec<u8> {
+        let mut out = Encoder::default();
+        self.encode(&mut out);
+        out.into_bytes()
+    }
+
+    fn restore(bytes: &[u8]) -> Result<Self, RestoreError> {
+        let mut input = Decoder::new(bytes);
+        let state = Self::decode(&mut input)?;
+        input.finish()?;
+        Ok(state)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreError {
+    Truncated,
+    WrongTag { expected: [u8; 4], found: [u8; 4] },
+    Invalid(&'static str),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestoreError::Truncated => write!(f, "checkpoint is truncated"),
+            RestoreError::WrongTag { expected, found } => write!(
+                f,
+                "checkpoint is for `{}`, expected `{}`",
+                found.escape_ascii(),
+                expected.escape_ascii()
+            ),
+            RestoreError::Invalid(what) => write!(f, "invalid checkpoint: {what}"),
+            RestoreError::TrailingBytes(n) => write!(f, "{n} unexpected bytes after checkpoint"),
+        }
+    }
+}
+
+impl Error for RestoreError {}
+
+/// Little endian writer for [`Checkpoint::encode`].
+#[derive(Debug, Clone, Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn tag(&mut self, tag: &[u8; 4]) {
+        self.bytes.extend_from_slice(tag);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(u8::from(value));
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// bit exact, NaN payloads included
+    pub fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+
+    pub fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+
+    pub fn duration(&mut self, value: Duration) {
+        self.u64(value.as_secs());
+        self.u64(u64::from(value.subsec_nanos()));
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.len(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reader matching [`Encoder`], every read fails with [`RestoreError::Truncated`] past the end.
+#[derive(Debug, Clone)]
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], RestoreError> {
+        let (taken, rest) = self
+            .bytes
+            .split_at_checked(n)
+            .ok_or(RestoreError::Truncated)?;
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn tag(&mut self, expected: &[u8; 4]) -> Result<(), RestoreError> {
+        let found: [u8; 4] = self.take(4)?.try_into().expect("took 4 bytes");
+        if &found != expected {
+            return Err(RestoreError::WrongTag {
+                expected: *expected,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, RestoreError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, RestoreError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(RestoreError::Invalid("boolean out of range")),
+        }
+    }
+
+    pub fn u64(&mut self) -> Result<u64, RestoreError> {
+        Ok(u64::from_le_bytes(
+            self.take(8)?.try_into().expect("took 8 bytes"),
+        ))
+    }
+
+    pub fn i64(&mut self) -> Result<i64, RestoreError> {
+        Ok(i64::from_le_bytes(
+            self.take(8)?.try_into().expect("took 8 bytes"),
+        ))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, RestoreError> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    /// a length of elements that take at least `min_element_size` bytes each,
+    /// checked against the remaining input so corrupt lengths can not cause huge allocations
+    pub fn len(&mut self, min_element_size: usize) -> Result<usize, RestoreError> {
+        let len = usize::try_from(self.u64()?).map_err(|_| RestoreError::Truncated)?;
+        if len.saturating_mul(min_element_size) > self.bytes.len() {
+            return Err(RestoreError::Truncated);
+        }
+        Ok(len)
+    }
+
+    pub fn duration(&mut self) -> Result<Duration, RestoreError> {
+        let secs = self.u64()?;
+        let nanos = u32::try_from(self.u64()?)
+            .ok()
+            .filter(|&n| n < 1_000_000_000)
+            .ok_or(RestoreError::Invalid("duration nanoseconds out of range"))?;
+        Ok(Duration::new(secs, nanos))
+    }
+
+    pub fn str(&mut self) -> Result<&'a str, RestoreError> {
+        let len = self.len(1)?;
+        std::str::from_utf8(self.take(len)?)
+            .map_err(|_| RestoreError::Invalid("string is not UTF-8"))
+    }
+
+    pub fn finish(self) -> Result<(), RestoreError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            n => Err(RestoreError::TrailingBytes(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{
+    AiController, CumulativeAverage, CusumDetector, CusumParams, ExponentialMovingAverage,
+    IqrDetector, MadDetector, MovingAverage, Pipeline, RollingStats, ThresholdDetector, TimeWindow,
+    WeightedMovingAverage, ZScoreDetector,
+};
+#[cfg(test)]
+use std::ops::{Coroutine, CoroutineState};
+#[cfg(test)]
+use std::pin::Pin;
+
+// runs the inputs through `stage` directly and through a copy restored at every split point
+#[cfg(test)]
+fn assert_restores_identically<S, A>(mut stage: S, inputs: &[A])
+where
+    S: Checkpoint + Coroutine<A> + Unpin,
+    S::Yield: PartialEq + fmt::Debug,
+    A: Clone,
+{
+    let resume = |stage: &mut S, input: &A| match Pin::new(stage).resume(input.clone()) {
+        CoroutineState::Yielded(output) => output,
+        CoroutineState::Complete(_) => panic!("stages never complete"),
+    };
+    let snapshots: Vec<Vec<u8>> = inputs
+        .iter()
+        .map(|input| {
+            let snapshot = stage.checkpoint();
+            resume(&mut stage, input);
+            snapshot
+        })
+        .collect();
+
+    for (split, snapshot) in snapshots.iter().enumerate() {
+        let mut original = S::restore(&snapshots[0]).unwrap();
+        let mut restored = S::restore(snapshot).unwrap();
+        let expected: Vec<S::Yield> = inputs.iter().map(|i| resume(&mut original, i)).collect();
+        let resumed: Vec<S::Yield> = inputs[split..]
+            .iter()
+            .map(|i| resume(&mut restored, i))
+            .collect();
+        assert_eq!(resumed, expected[split..], "restored before input {split}");
+    }
+}
+
+#[cfg(test)]
+fn series() -> Vec<f64> {
+    (0..40u64)
+        .map(|i| ((i * 7919) % 23) as f64 + if i == 30 { 100.0 } else { 0.0 })
+        .collect()
+}
+
+#[test]
+fn test_stages_continue_identically_after_restore() {
+    let data = series();
+    assert_restores_identically(MovingAverage::new(5), &data);
+    assert_restores_identically(ExponentialMovingAverage::new(0.3), &data);
+    assert_restores_identically(WeightedMovingAverage::new(4), &data);
+    assert_restores_identically(CumulativeAverage::new(), &data);
+    assert_restores_identically(ZScoreDetector::new(6, 2.0), &data);
+    assert_restores_identically(MadDetector::new(7, 3.5), &data);
+    assert_restores_identically(IqrDetector::new(8, 1.5), &data);
+    let params = CusumParams {
+        warmup: 5,
+        ..CusumParams::default()
+    };
+    assert_restores_identically(CusumDetector::new(params), &data);
+    assert_restores_identically(RollingStats::new(6).with_percentiles(&[0.1, 0.9]), &data);
+
+    let timed: Vec<(Duration, f64)> = data
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            (
+                Duration::from_millis((i as u64 * 370) % 9000 + i as u64 * 200),
+                v,
+            )
+        })
+        .collect();
+    let lateness = Duration::from_secs(2);
+    let second = Duration::from_secs(1);
+    assert_restores_identically(
+        TimeWindow::tumbling(second).with_allowed_lateness(lateness),
+        &timed,
+    );
+    assert_restores_identically(
+        TimeWindow::sliding(3 * second, second).with_allowed_lateness(lateness),
+        &timed,
+    );
+    assert_restores_identically(
+        TimeWindow::session(second / 2).with_allowed_lateness(lateness),
+        &timed,
+    );
+}
+
+#[test]
+fn test_whole_pipeline_restores() {
+    let pipeline = Pipeline::new(MovingAverage::new(3))
+        .with_input()
+        .then(ThresholdDetector::new(5.0));
+    assert_restores_identically(pipeline, &series());
+
+    let pair = Pipeline::new(CumulativeAverage::new())
+        .fan_out(MovingAverage::new(2), ExponentialMovingAverage::new(0.5));
+    assert_restores_identically(pair, &series());
+    let fan_out = Pipeline::new(CumulativeAverage::new())
+        .fan_out_all(vec![MovingAverage::new(2), MovingAverage::new(5)]);
+    assert_restores_identically(fan_out, &series());
+}
+
+#[test]
+fn test_corrupt_checkpoints_are_rejected() {
+    let mut average = MovingAverage::new(3);
+    average.push(1.0);
+    let bytes = average.checkpoint();
+
+    assert_eq!(
+        ZScoreDetector::restore(&bytes),
+        Err(RestoreError::WrongTag {
+            expected: *b"ZSC1",
+            found: *b"SMA1"
+        })
+    );
+    assert_eq!(
+        MovingAverage::restore(&bytes[..bytes.len() - 1]),
+        Err(RestoreError::Truncated)
+    );
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(
+        MovingAverage::restore(&longer),
+        Err(RestoreError::TrailingBytes(1))
+    );
+
+    // window of capacity 0
+    let mut zero_window = bytes.clone();
+    zero_window[4..12].copy_from_slice(&0u64.to_le_bytes());
+    assert!(matches!(
+        MovingAverage::restore(&zero_window),
+        Err(RestoreError::Invalid(_))
+    ));
+    // a window no stage could have been built with
+    let mut huge_window = bytes.clone();
+    huge_window[4..12].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(
+        MovingAverage::restore(&huge_window),
+        Err(RestoreError::Invalid(_))
+    ));
+    // a huge length must not be allocated
+    let mut huge = bytes;
+    huge[20..28].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(MovingAverage::restore(&huge), Err(RestoreError::Truncated));
+}
+
+#[test]
+fn test_inconsistent_states_are_rejected() {
+    let params = CusumParams {
+        warmup: 5,
+        ..CusumParams::default()
+    };
+    let mut cusum = CusumDetector::new(params);
+    cusum.push(1.0);
+    // learned lives at bytes 28..36, after the tag and the parameters
+    let mut overlearned = cusum.checkpoint();
+    overlearned[28..36].copy_from_slice(&6u64.to_le_bytes());
+    assert!(matches!(
+        CusumDetector::restore(&overlearned),
+        Err(RestoreError::Invalid(_))
+    ));
+
+    // phase at byte 4, hp at bytes 5..13
+    let bytes = AiController::new().checkpoint();
+    let mut negative_hp = bytes.clone();
+    negative_hp[5..13].copy_from_slice(&(-5i64).to_le_bytes());
+    assert!(matches!(
+        AiController::restore(&negative_hp),
+        Err(RestoreError::Invalid(_))
+    ));
+    let mut defeated_alive = bytes;
+    defeated_alive[4] = 2;
+    assert!(matches!(
+        AiController::restore(&defeated_alive),
+        Err(RestoreError::Invalid(_))
+    ));
+}
@@ -1,12 +1,59 @@
+// stateful stages that never return, resuming one pushes the argument and yields the result
+macro_rules! push_stage {
+    ($($stage:ty => $yield:ty),* $(,)?) => {
+        push_stage!($($stage: f64 => $yield),*);
+    };
+    ($($stage:ty: $arg:ty => $yield:ty),* $(,)?) => {$(
+        impl std::ops::Coroutine<$arg> for $stage {
+            type Yield = $yield;
+            type Return = ();
+
+            fn resume(
+                self: std::pin::Pin<&mut Self>,
+                arg: $arg,
+            ) -> std::ops::CoroutineState<$yield, ()> {
+                std::ops::CoroutineState::Yielded(self.get_mut().push(arg))
+            }
+        }
+    )*};
+}
+
+mod averages;
+mod io;
+mod outliers;
+mod pipeline;
+mod rolling;
+mod time_windows;
+
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+pub use averages::{
+    CompensatedSum, CumulativeAverage, ExponentialMovingAverage, MovingAverage,
+    WeightedMovingAverage, cumulative_average_coroutine, ewma_coroutine,
+    weighted_moving_average_coroutine,
+};
+pub use io::{
+    AnnotatedRow, AnnotatedWriter, Column, CsvOptions, InputError, InputFormat, OutputFormat,
+    SeriesReader, Timestamped, Values,
+};
+pub use outliers::{
+    CusumDetector, CusumParams, Direction, IqrDetector, MadDetector, OutlierReport, ZScoreDetector,
+    cusum_detector_coroutine, iqr_detector_coroutine, mad_detector_coroutine,
+    zscore_detector_coroutine,
+};
+pub use pipeline::{FanOut, Pipeline, PipelineRun, StageEnd, Then, WithInput};
+pub use rolling::{RollingStats, WindowStats, rolling_stats_coroutine};
 use std::ops::Coroutine;
 #[cfg(test)]
 use std::ops::CoroutineState;
 #[cfg(test)]
 use std::pin::Pin;
+pub use time_windows::{
+    TimeAggregate, TimeWindow, WindowKind, session_window_coroutine, sliding_window_coroutine,
+    tumbling_window_coroutine,
+};
 
 /// those coroutines never return, as they have infinite loops in them,
 /// it is fine as at each step of the loop there is yield
-
 pub fn outlier_detector_coroutine(
     threshold: f64,
 ) -> impl Coroutine<(f64, f64), Yield = Option<f64>, Return = ()> {
@@ -29,27 +76,51 @@ pub fn outlier_detector_coroutine(
     }
 }
 
+/// Same rule as [`outlier_detector_coroutine`] as a named stage,
+/// so pipelines using it can be checkpointed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThresholdDetector {
+    threshold: f64,
+}
+
+impl ThresholdDetector {
+    pub fn new(threshold: f64) -> Self {
+        ThresholdDetector { threshold }
+    }
+
+    pub fn push(&mut self, (value, average): (f64, f64)) -> Option<f64> {
+        ((value - average).abs() > self.threshold).then_some(value)
+    }
+}
+
+push_stage!(ThresholdDetector: (f64, f64) => Option<f64>);
+
+impl Checkpoint for ThresholdDetector {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"THR1");
+        out.f64(self.threshold);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"THR1")?;
+        Ok(ThresholdDetector::new(input.f64()?))
+    }
+}
+
+/// O(1) per sample, see [`MovingAverage`]; panics right away if `window_size` is 0 or above 2^24
 pub fn moving_average_coroutine(
     window_size: usize,
 ) -> impl Coroutine<f64, Yield = f64, Return = ()> {
+    // built here rather than inside, so a bad size panics at the call and not on the first resume
+    let mut average = MovingAverage::new(window_size);
+
     #[coroutine]
     // this will be argument passed to resume before any yield was handled
     move |mut value: f64| {
-        let mut window: Vec<f64> = Vec::with_capacity(window_size);
-        let mut sum: f64 = 0.0;
-
         loop {
-            sum += value;
-            window.push(value);
-
-            if window.len() > window_size {
-                sum -= window.remove(0);
-            }
-
-            let average = sum / window.len() as f64;
             // this actually updates input to be the argument passed to subsequent resume() call
             // so it is yield the average and assigning arguments from the next call to resume to value
-            value = yield average;
+            value = yield average.push(value);
         }
     }
 }
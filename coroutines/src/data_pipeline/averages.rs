@@ -0,0 +1,464 @@
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+use std::ops::Coroutine;
+
+/// Neumaier compensated summation, keeps the rounding error of every addition
+/// so long streams of values of very different magnitude do not drift.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, value: f64) {
+        let total = self.sum + value;
+        if self.sum.abs() >= value.abs() {
+            self.compensation += (self.sum - total) + value;
+        } else {
+            self.compensation += (value - total) + self.sum;
+        }
+        self.sum = total;
+    }
+
+    pub fn sub(&mut self, value: f64) {
+        self.add(-value);
+    }
+
+    pub fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+impl FromIterator<f64> for CompensatedSum {
+    fn from_iter<I: IntoIterator<Item = f64>>(values: I) -> Self {
+        let mut sum = CompensatedSum::new();
+        values.into_iter().for_each(|v| sum.add(v));
+        sum
+    }
+}
+
+// largest window a stage may be built or restored with, 128 MiB of samples
+pub(super) const MAX_WINDOW: usize = 1 << 24;
+
+/// Fixed size ring buffer, overwrites the oldest value once full.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Ring {
+    values: Vec<f64>,
+    capacity: usize,
+    // index of the oldest value once the ring is full
+    next: usize,
+}
+
+impl Ring {
+    pub(super) fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "window size must be at least 1");
+        assert!(
+            capacity <= MAX_WINDOW,
+            "window size must be at most {MAX_WINDOW}"
+        );
+        Ring {
+            values: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    /// returns the value that fell out of the window
+    pub(super) fn push(&mut self, value: f64) -> Option<f64> {
+        if self.values.len() < self.capacity {
+            self.values.push(value);
+            return None;
+        }
+        let evicted = std::mem::replace(&mut self.values[self.next], value);
+        self.next = (self.next + 1) % self.capacity;
+        Some(evicted)
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.values.len() == self.capacity
+    }
+
+    // true every time the whole window has been replaced since the last wrap
+    pub(super) fn wrapped(&self) -> bool {
+        self.values.len() == self.capacity && self.next == 0
+    }
+
+    /// oldest to newest
+    pub(super) fn iter(&self) -> impl Iterator<Item = f64> + '_ {
+        let (newer, older) = self.values.split_at(self.next);
+        older.iter().chain(newer).copied()
+    }
+}
+
+/// Simple moving average over the last `window_size` values, O(1) per sample.
+///
+/// The sum is compensated and additionally recomputed from the window every
+/// `window_size` samples, so subtracting evicted values can not accumulate error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovingAverage {
+    window: Ring,
+    sum: CompensatedSum,
+}
+
+impl MovingAverage {
+    /// panics if `window_size` is 0 or above 2^24
+    pub fn new(window_size: usize) -> Self {
+        MovingAverage {
+            window: Ring::new(window_size),
+            sum: CompensatedSum::new(),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) -> f64 {
+        match self.window.push(value) {
+            Some(_) if self.window.wrapped() => self.sum = self.window.iter().collect(),
+            Some(evicted) => {
+                self.sum.add(value);
+                self.sum.sub(evicted);
+            }
+            None => self.sum.add(value),
+        }
+        self.sum.value() / self.window.len() as f64
+    }
+}
+
+/// Exponentially weighted moving average, `alpha` is the weight of the newest value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExponentialMovingAverage {
+    alpha: f64,
+    current: Option<f64>,
+}
+
+impl ExponentialMovingAverage {
+    /// panics unless `0 < alpha <= 1`
+    pub fn new(alpha: f64) -> Self {
+        assert!(alpha > 0.0 && alpha <= 1.0, "alpha must be in (0, 1]");
+        ExponentialMovingAverage {
+            alpha,
+            current: None,
+        }
+    }
+
+    /// the usual `2 / (span + 1)` smoothing, comparable to a window of `span` values
+    pub fn with_span(span: usize) -> Self {
+        Self::new(2.0 / (span as f64 + 1.0))
+    }
+
+    /// the first value is taken as is
+    pub fn push(&mut self, value: f64) -> f64 {
+        let next = match self.current {
+            Some(current) => current + self.alpha * (value - current),
+            None => value,
+        };
+        self.current = Some(next);
+        next
+    }
+}
+
+/// Linearly weighted moving average, the newest value has weight `n`,
+/// the oldest of the `n` values in the window weight 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedMovingAverage {
+    window: Ring,
+    // plain sum of the window
+    total: CompensatedSum,
+    // sum of value * weight
+    numerator: CompensatedSum,
+}
+
+impl WeightedMovingAverage {
+    /// panics if `window_size` is 0 or above 2^24
+    pub fn new(window_size: usize) -> Self {
+        WeightedMovingAverage {
+            window: Ring::new(window_size),
+            total: CompensatedSum::new(),
+            numerator: CompensatedSum::new(),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) -> f64 {
+        // every value already in the window loses one unit of weight
+        let shifted = self.total.value();
+        match self.window.push(value) {
+            Some(_) if self.window.wrapped() => self.recompute(),
+            Some(evicted) => {
+                let n = self.window.len() as f64;
+                self.numerator.add(n * value);
+                self.numerator.sub(shifted);
+                self.total.add(value);
+                self.total.sub(evicted);
+            }
+            None => {
+                let n = self.window.len() as f64;
+                self.numerator.add(n * value);
+                self.total.add(value);
+            }
+        }
+        let n = self.window.len() as f64;
+        self.numerator.value() / (n * (n + 1.0) / 2.0)
+    }
+
+    fn recompute(&mut self) {
+        self.total = self.window.iter().collect();
+        self.numerator = self
+            .window
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i + 1) as f64 * v)
+            .collect();
+    }
+}
+
+/// Average of every value seen so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CumulativeAverage {
+    count: u64,
+    sum: CompensatedSum,
+}
+
+impl CumulativeAverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, value: f64) -> f64 {
+        self.count += 1;
+        self.sum.add(value);
+        self.sum.value() / self.count as f64
+    }
+}
+
+push_stage!(
+    MovingAverage => f64,
+    ExponentialMovingAverage => f64,
+    WeightedMovingAverage => f64,
+    CumulativeAverage => f64,
+);
+
+impl Checkpoint for CompensatedSum {
+    fn encode(&self, out: &mut Encoder) {
+        out.f64(self.sum);
+        out.f64(self.compensation);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        Ok(CompensatedSum {
+            sum: input.f64()?,
+            compensation: input.f64()?,
+        })
+    }
+}
+
+impl Checkpoint for Ring {
+    fn encode(&self, out: &mut Encoder) {
+        out.len(self.capacity);
+        out.len(self.next);
+        out.len(self.values.len());
+        self.values.iter().for_each(|&v| out.f64(v));
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        let capacity = input.len(0)?;
+        if capacity > MAX_WINDOW {
+            return Err(RestoreError::Invalid("window larger than any stage allows"));
+        }
+        let next = input.len(0)?;
+        let len = input.len(8)?;
+        let values = (0..len)
+            .map(|_| input.f64())
+            .collect::<Result<Vec<_>, _>>()?;
+        // `next` only moves once the ring is full
+        if capacity == 0 || len > capacity || next >= capacity || (len < capacity && next != 0) {
+            return Err(RestoreError::Invalid("inconsistent window"));
+        }
+        // not `Ring::new`, nothing is reserved beyond the values actually restored
+        Ok(Ring {
+            values,
+            capacity,
+            next,
+        })
+    }
+}
+
+impl Checkpoint for MovingAverage {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"SMA1");
+        self.window.encode(out);
+        self.sum.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"SMA1")?;
+        Ok(MovingAverage {
+            window: Ring::decode(input)?,
+            sum: CompensatedSum::decode(input)?,
+        })
+    }
+}
+
+impl Checkpoint for ExponentialMovingAverage {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"EMA1");
+        out.f64(self.alpha);
+        out.bool(self.current.is_some());
+        out.f64(self.current.unwrap_or_default());
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"EMA1")?;
+        let alpha = input.f64()?;
+        let started = input.bool()?;
+        let current = input.f64()?;
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            return Err(RestoreError::Invalid("alpha out of range"));
+        }
+        Ok(ExponentialMovingAverage {
+            alpha,
+            current: started.then_some(current),
+        })
+    }
+}
+
+impl Checkpoint for WeightedMovingAverage {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"WMA1");
+        self.window.encode(out);
+        self.total.encode(out);
+        self.numerator.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"WMA1")?;
+        Ok(WeightedMovingAverage {
+            window: Ring::decode(input)?,
+            total: CompensatedSum::decode(input)?,
+            numerator: CompensatedSum::decode(input)?,
+        })
+    }
+}
+
+impl Checkpoint for CumulativeAverage {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"CMA1");
+        out.u64(self.count);
+        self.sum.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"CMA1")?;
+        Ok(CumulativeAverage {
+            count: input.u64()?,
+            sum: CompensatedSum::decode(input)?,
+        })
+    }
+}
+
+pub fn ewma_coroutine(alpha: f64) -> impl Coroutine<f64, Yield = f64, Return = ()> {
+    ExponentialMovingAverage::new(alpha)
+}
+
+pub fn weighted_moving_average_coroutine(
+    window_size: usize,
+) -> impl Coroutine<f64, Yield = f64, Return = ()> {
+    WeightedMovingAverage::new(window_size)
+}
+
+pub fn cumulative_average_coroutine() -> impl Coroutine<f64, Yield = f64, Return = ()> {
+    CumulativeAverage::new()
+}
+
+#[cfg(test)]
+use crate::Pipeline;
+
+#[cfg(test)]
+fn naive_window_average(data: &[f64], end: usize, window_size: usize) -> f64 {
+    let window = &data[end.saturating_sub(window_size)..end];
+    window.iter().sum::<f64>() / window.len() as f64
+}
+
+#[test]
+fn test_moving_average_matches_naive() {
+    let data: Vec<f64> = (0..200).map(|i| ((i * 37) % 101) as f64 - 50.0).collect();
+    for window_size in [1, 2, 7, 64, 500] {
+        let mut average = MovingAverage::new(window_size);
+        for (i, &value) in data.iter().enumerate() {
+            let expected = naive_window_average(&data, i + 1, window_size);
+            assert!((average.push(value) - expected).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_compensated_sum_does_not_lose_small_values() {
+    // a plain running sum gives 0 here, the 1.0 values are absorbed by 1e16
+    let mut average = MovingAverage::new(2);
+    average.push(1e16);
+    average.push(1.0);
+    assert_eq!(average.push(1.0), 1.0);
+
+    let sum: CompensatedSum = [1e16, 1.0, -1e16, 1.0].into_iter().collect();
+    assert_eq!(sum.value(), 2.0);
+}
+
+#[test]
+fn test_moving_average_long_stream_stays_exact() {
+    let mut average = MovingAverage::new(10);
+    let mut last = 0.0;
+    for i in 0..1_000_000u64 {
+        // large offsets come and go, the final window only holds 0.1
+        let value = if i < 999_990 && i % 3 == 0 {
+            1e12 + 0.3
+        } else {
+            0.1
+        };
+        last = average.push(value);
+    }
+    assert!((last - 0.1).abs() < 1e-12, "drifted to {last}");
+}
+
+#[test]
+fn test_other_averages() {
+    let mut ewma = ExponentialMovingAverage::new(0.5);
+    let values: Vec<f64> = [4.0, 8.0, 0.0].into_iter().map(|v| ewma.push(v)).collect();
+    assert_eq!(values, vec![4.0, 6.0, 3.0]);
+    assert_eq!(ExponentialMovingAverage::with_span(3).alpha, 0.5);
+
+    let data: Vec<f64> = (0..50).map(|i| ((i * 13) % 17) as f64).collect();
+    let mut wma = WeightedMovingAverage::new(4);
+    for (i, &value) in data.iter().enumerate() {
+        let window = &data[(i + 1).saturating_sub(4)..=i];
+        let weights = (1..=window.len()).map(|w| w as f64);
+        let expected = window
+            .iter()
+            .zip(weights.clone())
+            .map(|(v, w)| v * w)
+            .sum::<f64>()
+            / weights.sum::<f64>();
+        assert!((wma.push(value) - expected).abs() < 1e-9);
+    }
+
+    let cumulative: Vec<f64> = Pipeline::new(cumulative_average_coroutine())
+        .run([1.0, 2.0, 3.0, 10.0])
+        .collect();
+    assert_eq!(cumulative, vec![1.0, 1.5, 2.0, 4.0]);
+}
+
+#[test]
+#[should_panic(expected = "window size must be at least 1")]
+fn test_empty_window_is_rejected() {
+    MovingAverage::new(0);
+}
+
+#[test]
+#[should_panic(expected = "window size must be at least 1")]
+fn test_empty_window_coroutine_panics_before_resume() {
+    let _never_resumed = crate::moving_average_coroutine(0);
+}
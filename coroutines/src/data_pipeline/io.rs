@@ -0,0 +1,535 @@
+use super::outliers::OutlierReport;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+/// Reading a series failed, parse errors carry the 1-based line number.
+#[derive(Debug)]
+pub enum InputError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Io(e) => write!(f, "failed to read input: {e}"),
+            InputError::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl Error for InputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InputError::Io(e) => Some(e),
+            InputError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for InputError {
+    fn from(e: io::Error) -> Self {
+        InputError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// 0-based
+    Index(usize),
+    /// looked up in the header line
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub has_header: bool,
+    pub value_column: Column,
+    /// only needed for [`SeriesReader::timestamped`]
+    pub timestamp_column: Column,
+}
+
+impl Default for CsvOptions {
+    /// `timestamp,value` with a header line
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            has_header: true,
+            value_column: Column::Index(1),
+            timestamp_column: Column::Index(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputFormat {
+    /// one value per line, or `timestamp value` separated by whitespace or a comma
+    Lines,
+    /// fields are split on the delimiter and trimmed, surrounding quotes are removed;
+    /// quoted fields containing the delimiter are not supported
+    Csv(CsvOptions),
+}
+
+/// Streams a series out of any buffered reader, one line at a time.
+///
+/// Blank lines and lines starting with `#` are skipped, timestamps are seconds
+/// (fractions allowed) since any fixed origin.
+pub struct SeriesReader<R> {
+    reader: R,
+    format: InputFormat,
+    line: String,
+    line_number: usize,
+    // value and timestamp column once the header is read
+    columns: Option<(usize, Option<usize>)>,
+}
+
+impl<R: BufRead> SeriesReader<R> {
+    pub fn new(reader: R, format: InputFormat) -> Self {
+        SeriesReader {
+            reader,
+            format,
+            line: String::new(),
+            line_number: 0,
+            columns: None,
+        }
+    }
+
+    pub fn values(self) -> Values<R> {
+        Values(self)
+    }
+
+    pub fn timestamped(self) -> Timestamped<R> {
+        Timestamped(self)
+    }
+
+    // reads the next line with content into `self.line`, false at the end of the input
+    fn next_line(&mut self) -> Result<bool, InputError> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return Ok(false),
+                Ok(_) => self.line_number += 1,
+                // the line is consumed all the same, so reading goes on with the next one
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    self.line_number += 1;
+                    return Err(self.error("line is not valid UTF-8".to_string()));
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let content = self.line.trim();
+            if !content.is_empty() && !content.starts_with('#') {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn next_record(
+        &mut self,
+        with_timestamp: bool,
+    ) -> Option<Result<(Option<Duration>, f64), InputError>> {
+        match self.next_line() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+        if let InputFormat::Csv(options) = &self.format
+            && options.has_header
+            && self.columns.is_none()
+        {
+            let options = options.clone();
+            if let Err(e) = self.read_header(&options, with_timestamp) {
+                return Some(Err(e));
+            }
+            return self.next_record(with_timestamp);
+        }
+        Some(self.parse_record(with_timestamp))
+    }
+
+    fn read_header(
+        &mut self,
+        options: &CsvOptions,
+        with_timestamp: bool,
+    ) -> Result<(), InputError> {
+        let names: Vec<&str> = split_csv(&self.line, options.delimiter).collect();
+        let find = |column: &Column| match column {
+            Column::Index(index) => Ok(*index),
+            Column::Name(name) => names
+                .iter()
+                .position(|n| n == name)
+                .ok_or_else(|| format!("no column named `{name}` in the header")),
+        };
+        let value = find(&options.value_column);
+        let timestamp = with_timestamp
+            .then(|| find(&options.timestamp_column))
+            .transpose();
+        match (value, timestamp) {
+            (Ok(value), Ok(timestamp)) => {
+                self.columns = Some((value, timestamp));
+                Ok(())
+            }
+            (Err(message), _) | (_, Err(message)) => Err(self.error(message)),
+        }
+    }
+
+    fn parse_record(
+        &mut self,
+        with_timestamp: bool,
+    ) -> Result<(Option<Duration>, f64), InputError> {
+        let fields: Vec<&str> = match &self.format {
+            InputFormat::Lines if with_timestamp => self
+                .line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|f| !f.is_empty())
+                .collect(),
+            InputFormat::Lines => vec![self.line.trim()],
+            InputFormat::Csv(options) => split_csv(&self.line, options.delimiter).collect(),
+        };
+        let (value_column, timestamp_column) = match (&self.format, self.columns) {
+            (_, Some(columns)) => columns,
+            (InputFormat::Lines, None) if with_timestamp => {
+                if fields.len() != 2 {
+                    return Err(self.error(format!(
+                        "expected `timestamp value`, found {} fields",
+                        fields.len()
+                    )));
+                }
+                (1, Some(0))
+            }
+            (InputFormat::Lines, None) => (0, None),
+            (InputFormat::Csv(options), None) => {
+                let index = |column: &Column| match column {
+                    Column::Index(index) => Some(*index),
+                    Column::Name(_) => None,
+                };
+                let timestamp = with_timestamp.then(|| index(&options.timestamp_column));
+                match (index(&options.value_column), timestamp) {
+                    (Some(value), None) => (value, None),
+                    (Some(value), Some(Some(timestamp))) => (value, Some(timestamp)),
+                    _ => {
+                        return Err(self.error(
+                            "columns can only be selected by name with a header line".into(),
+                        ));
+                    }
+                }
+            }
+        };
+
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .copied()
+                .ok_or_else(|| format!("missing column {index}"))
+        };
+        let value = field(value_column).and_then(|f| {
+            f.parse::<f64>()
+                .map_err(|_| format!("expected a number, found `{f}`"))
+        });
+        let timestamp = timestamp_column
+            .map(|column| {
+                field(column).and_then(|f| {
+                    f.parse::<f64>()
+                        .ok()
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .ok_or_else(|| format!("expected a timestamp in seconds, found `{f}`"))
+                })
+            })
+            .transpose();
+        match (timestamp, value) {
+            (Ok(timestamp), Ok(value)) => Ok((timestamp, value)),
+            (Err(message), _) | (_, Err(message)) => Err(self.error(message)),
+        }
+    }
+
+    fn error(&self, message: String) -> InputError {
+        InputError::Parse {
+            line: self.line_number,
+            message,
+        }
+    }
+}
+
+fn split_csv(line: &str, delimiter: char) -> impl Iterator<Item = &str> {
+    line.trim_end_matches(['\r', '\n'])
+        .split(delimiter)
+        .map(|field| {
+            let field = field.trim();
+            field
+                .strip_prefix('"')
+                .and_then(|f| f.strip_suffix('"'))
+                .unwrap_or(field)
+        })
+}
+
+/// Iterator over the values of a [`SeriesReader`].
+pub struct Values<R>(SeriesReader<R>);
+
+impl<R: BufRead> Iterator for Values<R> {
+    type Item = Result<f64, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.0.next_record(false)?.map(|(_, value)| value))
+    }
+}
+
+/// Iterator over the `(timestamp, value)` records of a [`SeriesReader`],
+/// ready to be fed into the time window stages.
+pub struct Timestamped<R>(SeriesReader<R>);
+
+impl<R: BufRead> Iterator for Timestamped<R> {
+    type Item = Result<(Duration, f64), InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(
+            self.0
+                .next_record(true)?
+                .map(|(timestamp, value)| (timestamp.expect("timestamp column is parsed"), value)),
+        )
+    }
+}
+
+/// One output line: a value, the baseline it was compared to and the verdict.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnnotatedRow {
+    pub timestamp: Option<Duration>,
+    pub value: f64,
+    pub average: f64,
+    pub outlier: bool,
+}
+
+impl From<OutlierReport> for AnnotatedRow {
+    fn from(report: OutlierReport) -> Self {
+        AnnotatedRow {
+            timestamp: None,
+            value: report.value,
+            average: report.baseline,
+            outlier: report.is_outlier,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `[timestamp,]value,average,outlier` with a header line
+    Csv,
+    /// one JSON object per line, non finite numbers are written as `null`
+    Ndjson,
+}
+
+/// Writes annotated rows as they come, nothing is buffered beyond the inner writer.
+pub struct AnnotatedWriter<W> {
+    writer: W,
+    format: OutputFormat,
+    header_written: bool,
+}
+
+impl<W: Write> AnnotatedWriter<W> {
+    pub fn new(writer: W, format: OutputFormat) -> Self {
+        AnnotatedWriter {
+            writer,
+            format,
+            header_written: false,
+        }
+    }
+
+    /// the CSV header is derived from the first row, it has a timestamp column if that row has one
+    pub fn write(&mut self, row: &AnnotatedRow) -> io::Result<()> {
+        let timestamp = row.timestamp.map(|t| t.as_secs_f64());
+        match self.format {
+            OutputFormat::Csv => {
+                if !self.header_written {
+                    let timestamp_header = if timestamp.is_some() {
+                        "timestamp,"
+                    } else {
+                        ""
+                    };
+                    writeln!(self.writer, "{timestamp_header}value,average,outlier")?;
+                    self.header_written = true;
+                }
+                if let Some(timestamp) = timestamp {
+                    write!(self.writer, "{timestamp},")?;
+                }
+                writeln!(self.writer, "{},{},{}", row.value, row.average, row.outlier)
+            }
+            OutputFormat::Ndjson => {
+                write!(self.writer, "{{")?;
+                if let Some(timestamp) = timestamp {
+                    write!(self.writer, "\"timestamp\":{},", JsonNumber(timestamp))?;
+                }
+                writeln!(
+                    self.writer,
+                    "\"value\":{},\"average\":{},\"outlier\":{}}}",
+                    JsonNumber(row.value),
+                    JsonNumber(row.average),
+                    row.outlier
+                )
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+struct JsonNumber(f64);
+
+impl fmt::Display for JsonNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_finite() {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "null")
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{Pipeline, mad_detector_coroutine, tumbling_window_coroutine};
+
+#[test]
+fn test_read_lines_with_parse_error_line_numbers() {
+    let input = "1.5\n\n# comment\n2\n  -3e1  \nabc\n4\n";
+    let values: Vec<Result<f64, InputError>> =
+        SeriesReader::new(input.as_bytes(), InputFormat::Lines)
+            .values()
+            .collect();
+    assert_eq!(values.len(), 5);
+    let parsed: Vec<f64> = values
+        .iter()
+        .filter_map(|v| v.as_ref().ok().copied())
+        .collect();
+    assert_eq!(parsed, vec![1.5, 2.0, -30.0, 4.0]);
+    let error = values[3].as_ref().unwrap_err();
+    assert_eq!(error.to_string(), "line 6: expected a number, found `abc`");
+
+    let records: Vec<(Duration, f64)> =
+        SeriesReader::new("0.5 1\n1,2\r\n".as_bytes(), InputFormat::Lines)
+            .timestamped()
+            .collect::<Result<_, _>>()
+            .unwrap();
+    assert_eq!(
+        records,
+        vec![
+            (Duration::from_millis(500), 1.0),
+            (Duration::from_secs(1), 2.0)
+        ]
+    );
+}
+
+#[test]
+fn test_invalid_utf8_is_a_parse_error() {
+    let input: &[u8] = b"1\n2\xff\n3\n";
+    let values: Vec<Result<f64, InputError>> = SeriesReader::new(input, InputFormat::Lines)
+        .values()
+        .collect();
+    assert_eq!(values.len(), 3);
+    assert_eq!(
+        values[1].as_ref().unwrap_err().to_string(),
+        "line 2: line is not valid UTF-8"
+    );
+    assert_eq!(values[2].as_ref().unwrap(), &3.0);
+}
+
+#[test]
+fn test_read_csv_columns_by_name() {
+    let input = "sensor;\"time\";reading\na;1;10.5\na;2;11\nb;x;12\n";
+    let options = CsvOptions {
+        delimiter: ';',
+        value_column: Column::Name("reading".into()),
+        timestamp_column: Column::Name("time".into()),
+        ..CsvOptions::default()
+    };
+    let format = InputFormat::Csv(options);
+    let values: Vec<f64> = SeriesReader::new(input.as_bytes(), format.clone())
+        .values()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(values, vec![10.5, 11.0, 12.0]);
+
+    let records: Vec<_> = SeriesReader::new(input.as_bytes(), format)
+        .timestamped()
+        .collect();
+    assert!(matches!(records[1], Ok((t, 11.0)) if t == Duration::from_secs(2)));
+    assert_eq!(
+        records[2].as_ref().unwrap_err().to_string(),
+        "line 4: expected a timestamp in seconds, found `x`"
+    );
+
+    let missing = CsvOptions {
+        value_column: Column::Name("temperature".into()),
+        ..CsvOptions::default()
+    };
+    let mut values = SeriesReader::new("t,v\n1,2\n".as_bytes(), InputFormat::Csv(missing)).values();
+    assert_eq!(
+        values.next().unwrap().unwrap_err().to_string(),
+        "line 1: no column named `temperature` in the header"
+    );
+
+    let no_header = CsvOptions {
+        has_header: false,
+        ..CsvOptions::default()
+    };
+    let mut values = SeriesReader::new("1,2\n3\n".as_bytes(), InputFormat::Csv(no_header)).values();
+    assert_eq!(values.next().unwrap().unwrap(), 2.0);
+    assert_eq!(
+        values.next().unwrap().unwrap_err().to_string(),
+        "line 2: missing column 1"
+    );
+}
+
+#[test]
+fn test_stream_through_stages_into_writers() {
+    let input = "10\n10.2\n9.9\n10.1\n10\n55\n10\n";
+    let values = SeriesReader::new(input.as_bytes(), InputFormat::Lines)
+        .values()
+        .map(Result::unwrap);
+    let mut csv = AnnotatedWriter::new(Vec::new(), OutputFormat::Csv);
+    for report in Pipeline::new(mad_detector_coroutine(4, 3.5)).run(values) {
+        csv.write(&report.into()).unwrap();
+    }
+    let csv = String::from_utf8(csv.into_inner()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "value,average,outlier");
+    assert_eq!(lines[1], "10,10,false");
+    assert_eq!(lines[6], "55,10.05,true");
+
+    let mut ndjson = AnnotatedWriter::new(Vec::new(), OutputFormat::Ndjson);
+    let records = SeriesReader::new("1 2\n3 4\n12 6\n".as_bytes(), InputFormat::Lines)
+        .timestamped()
+        .map(Result::unwrap);
+    for window in Pipeline::new(tumbling_window_coroutine(
+        Duration::from_secs(10),
+        Duration::ZERO,
+    ))
+    .run(records)
+    .flatten()
+    {
+        let row = AnnotatedRow {
+            timestamp: Some(window.start),
+            value: window.max,
+            average: window.mean,
+            outlier: false,
+        };
+        ndjson.write(&row).unwrap();
+    }
+    ndjson
+        .write(&AnnotatedRow {
+            timestamp: None,
+            value: f64::NAN,
+            average: 1.0,
+            outlier: true,
+        })
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(ndjson.into_inner()).unwrap(),
+        "{\"timestamp\":0,\"value\":4,\"average\":3,\"outlier\":false}\n\
+         {\"value\":null,\"average\":1,\"outlier\":true}\n"
+    );
+}
@@ -0,0 +1,671 @@
+use super::averages::Ring;
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+use std::cmp::Ordering;
+use std::ops::Coroutine;
+
+/// Side of the baseline a value or a detected shift is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+/// What a detector concluded about one value.
+///
+/// `score` is the non negative distance from `baseline` in the detector's own unit
+/// (standard deviations, scaled MADs, IQRs or accumulated CUSUM), it is compared against
+/// the detector threshold. Until a detector has seen a full window the score is 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlierReport {
+    pub value: f64,
+    pub baseline: f64,
+    pub score: f64,
+    pub direction: Direction,
+    pub is_outlier: bool,
+}
+
+impl OutlierReport {
+    fn scored(value: f64, baseline: f64, score: f64, threshold: f64) -> Self {
+        OutlierReport {
+            value,
+            baseline,
+            score,
+            direction: direction(value, baseline),
+            is_outlier: score > threshold,
+        }
+    }
+
+    fn warming_up(value: f64, baseline: f64) -> Self {
+        Self::scored(value, baseline, 0.0, f64::INFINITY)
+    }
+}
+
+fn direction(value: f64, baseline: f64) -> Direction {
+    if value >= baseline {
+        Direction::Above
+    } else {
+        Direction::Below
+    }
+}
+
+// distance in units of `spread`, with no spread at all any difference is infinitely far
+fn scaled(distance: f64, spread: f64) -> f64 {
+    if spread > 0.0 {
+        distance.abs() / spread
+    } else if distance == 0.0 {
+        0.0
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// Mean and variance of a sliding window, updated with Welford's method in O(1)
+/// and recomputed from the window every time it has been fully replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct WindowMoments {
+    window: Ring,
+    mean: f64,
+    // sum of squared differences from the mean
+    m2: f64,
+}
+
+impl WindowMoments {
+    pub(super) fn new(window_size: usize) -> Self {
+        WindowMoments {
+            window: Ring::new(window_size),
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub(super) fn push(&mut self, value: f64) {
+        match self.window.push(value) {
+            Some(_) if self.window.wrapped() => self.recompute(),
+            Some(evicted) => {
+                let n = self.window.len() as f64;
+                // remove the evicted value from a window of n, then add the new one back
+                let delta = evicted - self.mean;
+                let mean = self.mean - delta / (n - 1.0);
+                self.m2 -= delta * (evicted - mean);
+                let delta = value - mean;
+                self.mean = mean + delta / n;
+                self.m2 += delta * (value - self.mean);
+                self.m2 = self.m2.max(0.0);
+            }
+            None => {
+                let delta = value - self.mean;
+                self.mean += delta / self.window.len() as f64;
+                self.m2 += delta * (value - self.mean);
+            }
+        }
+    }
+
+    fn recompute(&mut self) {
+        let n = self.window.len() as f64;
+        self.mean = self.window.iter().sum::<f64>() / n;
+        self.m2 = self.window.iter().map(|v| (v - self.mean).powi(2)).sum();
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.window.is_full()
+    }
+
+    pub(super) fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// sample variance, 0 for fewer than two values
+    pub(super) fn variance(&self) -> f64 {
+        match self.window.len() {
+            0 | 1 => 0.0,
+            n => self.m2 / (n - 1) as f64,
+        }
+    }
+}
+
+/// Sliding window that also keeps its values sorted, for medians and quantiles.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct SortedWindow {
+    window: Ring,
+    sorted: Vec<f64>,
+}
+
+impl SortedWindow {
+    pub(super) fn new(window_size: usize) -> Self {
+        SortedWindow {
+            window: Ring::new(window_size),
+            sorted: Vec::with_capacity(window_size),
+        }
+    }
+
+    pub(super) fn push(&mut self, value: f64) {
+        if let Some(evicted) = self.window.push(value) {
+            let index = self
+                .sorted
+                .binary_search_by(|v| v.total_cmp(&evicted))
+                .expect("evicted value is in the sorted window");
+            self.sorted.remove(index);
+        }
+        let index = self
+            .sorted
+            .partition_point(|v| v.total_cmp(&value) == Ordering::Less);
+        self.sorted.insert(index, value);
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.window.is_full()
+    }
+
+    pub(super) fn sorted(&self) -> &[f64] {
+        &self.sorted
+    }
+}
+
+/// linear interpolation between the closest ranks, `sorted` must not be empty
+pub(super) fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let position = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let low = position.floor() as usize;
+    let high = position.ceil() as usize;
+    sorted[low] + (sorted[high] - sorted[low]) * (position - low as f64)
+}
+
+/// Flags values more than `threshold` standard deviations away from the mean
+/// of the previous `window_size` values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZScoreDetector {
+    moments: WindowMoments,
+    threshold: f64,
+}
+
+impl ZScoreDetector {
+    /// panics if `window_size` is 0 or above 2^24
+    pub fn new(window_size: usize, threshold: f64) -> Self {
+        ZScoreDetector {
+            moments: WindowMoments::new(window_size),
+            threshold,
+        }
+    }
+
+    pub fn push(&mut self, value: f64) -> OutlierReport {
+        let report = if self.moments.is_full() {
+            let mean = self.moments.mean();
+            let score = scaled(value - mean, self.moments.variance().sqrt());
+            OutlierReport::scored(value, mean, score, self.threshold)
+        } else {
+            OutlierReport::warming_up(value, self.moments.mean())
+        };
+        self.moments.push(value);
+        report
+    }
+}
+
+/// Modified z-score (Iglewicz and Hoaglin): distance from the rolling median
+/// in units of the median absolute deviation, scaled to be comparable to a
+/// standard deviation for normal data. 3.5 is the usual threshold.
+///
+/// Unlike the mean and standard deviation, neither is pulled by the outliers
+/// themselves, so a burst of outliers does not hide the following ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MadDetector {
+    window: SortedWindow,
+    threshold: f64,
+}
+
+impl MadDetector {
+    // MAD of a normal distribution is 0.6745 standard deviations
+    const NORMAL_CONSISTENCY: f64 = 0.6745;
+
+    /// panics if `window_size` is 0 or above 2^24
+    pub fn new(window_size: usize, threshold: f64) -> Self {
+        MadDetector {
+            window: SortedWindow::new(window_size),
+            threshold,
+        }
+    }
+
+    /// O(window_size log window_size) per value, the deviations have to be sorted again
+    pub fn push(&mut self, value: f64) -> OutlierReport {
+        let report = if self.window.is_full() {
+            let median = quantile(self.window.sorted(), 0.5);
+            let mut deviations: Vec<f64> = self
+                .window
+                .sorted()
+                .iter()
+                .map(|v| (v - median).abs())
+                .collect();
+            deviations.sort_unstable_by(f64::total_cmp);
+            let mad = quantile(&deviations, 0.5);
+            let score = scaled(value - median, mad / Self::NORMAL_CONSISTENCY);
+            OutlierReport::scored(value, median, score, self.threshold)
+        } else {
+            OutlierReport::warming_up(value, value)
+        };
+        self.window.push(value);
+        report
+    }
+}
+
+/// Tukey fences: flags values more than `k` interquartile ranges below the first
+/// or above the third quartile of the previous window, 1.5 is the usual `k`.
+/// The score is the distance beyond the nearest quartile in IQRs, the baseline the median.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IqrDetector {
+    window: SortedWindow,
+    k: f64,
+}
+
+impl IqrDetector {
+    /// panics if `window_size` is 0 or above 2^24
+    pub fn new(window_size: usize, k: f64) -> Self {
+        IqrDetector {
+            window: SortedWindow::new(window_size),
+            k,
+        }
+    }
+
+    pub fn push(&mut self, value: f64) -> OutlierReport {
+        let report = if self.window.is_full() {
+            let sorted = self.window.sorted();
+            let (q1, median, q3) = (
+                quantile(sorted, 0.25),
+                quantile(sorted, 0.5),
+                quantile(sorted, 0.75),
+            );
+            let beyond = if value > q3 {
+                value - q3
+            } else if value < q1 {
+                q1 - value
+            } else {
+                0.0
+            };
+            OutlierReport::scored(value, median, scaled(beyond, q3 - q1), self.k)
+        } else {
+            OutlierReport::warming_up(value, value)
+        };
+        self.window.push(value);
+        report
+    }
+}
+
+/// Tuning of [`CusumDetector`], `drift` and `threshold` are in standard deviations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CusumParams {
+    /// values used to learn the reference mean and deviation, at least 2
+    pub warmup: usize,
+    /// allowed slack per value before deviations start to accumulate
+    pub drift: f64,
+    /// accumulated deviation that signals a change
+    pub threshold: f64,
+}
+
+impl Default for CusumParams {
+    fn default() -> Self {
+        CusumParams {
+            warmup: 30,
+            drift: 0.5,
+            threshold: 5.0,
+        }
+    }
+}
+
+/// Two sided CUSUM change-point detector.
+///
+/// Learns the reference level from the first `warmup` values, then accumulates
+/// standardized deviations above and below it. A report is flagged when either sum
+/// crosses the threshold, meaning the level shifted in `direction`; the detector then
+/// starts over and learns the new level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CusumDetector {
+    params: CusumParams,
+    learned: usize,
+    mean: f64,
+    m2: f64,
+    // mean and standard deviation once warmed up
+    reference: Option<(f64, f64)>,
+    upper: f64,
+    lower: f64,
+}
+
+impl CusumDetector {
+    /// panics if `params.warmup` is smaller than 2
+    pub fn new(params: CusumParams) -> Self {
+        assert!(params.warmup >= 2, "CUSUM warmup needs at least 2 values");
+        CusumDetector {
+            params,
+            learned: 0,
+            mean: 0.0,
+            m2: 0.0,
+            reference: None,
+            upper: 0.0,
+            lower: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, value: f64) -> OutlierReport {
+        let Some((mean, deviation)) = self.reference else {
+            self.learned += 1;
+            let delta = value - self.mean;
+            self.mean += delta / self.learned as f64;
+            self.m2 += delta * (value - self.mean);
+            if self.learned == self.params.warmup {
+                let deviation = (self.m2 / (self.learned - 1) as f64).sqrt();
+                self.reference = Some((self.mean, deviation));
+            }
+            return OutlierReport::warming_up(value, self.mean);
+        };
+
+        let z = match scaled(value - mean, deviation) {
+            distance if value >= mean => distance,
+            distance => -distance,
+        };
+        self.upper = (self.upper + z - self.params.drift).max(0.0);
+        self.lower = (self.lower - z - self.params.drift).max(0.0);
+        let report = OutlierReport {
+            value,
+            baseline: mean,
+            score: self.upper.max(self.lower),
+            direction: if self.upper >= self.lower {
+                Direction::Above
+            } else {
+                Direction::Below
+            },
+            is_outlier: self.upper.max(self.lower) > self.params.threshold,
+        };
+        if report.is_outlier {
+            *self = Self::new(self.params);
+        }
+        report
+    }
+}
+
+push_stage!(
+    ZScoreDetector => OutlierReport,
+    MadDetector => OutlierReport,
+    IqrDetector => OutlierReport,
+    CusumDetector => OutlierReport,
+);
+
+impl Checkpoint for WindowMoments {
+    fn encode(&self, out: &mut Encoder) {
+        self.window.encode(out);
+        out.f64(self.mean);
+        out.f64(self.m2);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        Ok(WindowMoments {
+            window: Ring::decode(input)?,
+            mean: input.f64()?,
+            m2: input.f64()?,
+        })
+    }
+}
+
+impl Checkpoint for SortedWindow {
+    // the sorted copy is rebuilt from the window
+    fn encode(&self, out: &mut Encoder) {
+        self.window.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        let window = Ring::decode(input)?;
+        let mut sorted: Vec<f64> = window.iter().collect();
+        sorted.sort_unstable_by(f64::total_cmp);
+        Ok(SortedWindow { window, sorted })
+    }
+}
+
+impl Checkpoint for ZScoreDetector {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"ZSC1");
+        self.moments.encode(out);
+        out.f64(self.threshold);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"ZSC1")?;
+        Ok(ZScoreDetector {
+            moments: WindowMoments::decode(input)?,
+            threshold: input.f64()?,
+        })
+    }
+}
+
+impl Checkpoint for MadDetector {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"MAD1");
+        self.window.encode(out);
+        out.f64(self.threshold);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"MAD1")?;
+        Ok(MadDetector {
+            window: SortedWindow::decode(input)?,
+            threshold: input.f64()?,
+        })
+    }
+}
+
+impl Checkpoint for IqrDetector {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"IQR1");
+        self.window.encode(out);
+        out.f64(self.k);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"IQR1")?;
+        Ok(IqrDetector {
+            window: SortedWindow::decode(input)?,
+            k: input.f64()?,
+        })
+    }
+}
+
+impl Checkpoint for CusumDetector {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"CSM1");
+        out.len(self.params.warmup);
+        out.f64(self.params.drift);
+        out.f64(self.params.threshold);
+        out.len(self.learned);
+        out.f64(self.mean);
+        out.f64(self.m2);
+        out.bool(self.reference.is_some());
+        let (mean, deviation) = self.reference.unwrap_or_default();
+        out.f64(mean);
+        out.f64(deviation);
+        out.f64(self.upper);
+        out.f64(self.lower);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"CSM1")?;
+        let params = CusumParams {
+            warmup: input.len(0)?,
+            drift: input.f64()?,
+            threshold: input.f64()?,
+        };
+        if params.warmup < 2 {
+            return Err(RestoreError::Invalid("CUSUM warmup below 2"));
+        }
+        let learned = input.len(0)?;
+        let (mean, m2) = (input.f64()?, input.f64()?);
+        let warmed_up = input.bool()?;
+        let reference = (input.f64()?, input.f64()?);
+        // learning stops exactly when the reference is taken
+        if (warmed_up && learned != params.warmup) || (!warmed_up && learned >= params.warmup) {
+            return Err(RestoreError::Invalid(
+                "CUSUM learned samples disagree with warmup",
+            ));
+        }
+        Ok(CusumDetector {
+            params,
+            learned,
+            mean,
+            m2,
+            reference: warmed_up.then_some(reference),
+            upper: input.f64()?,
+            lower: input.f64()?,
+        })
+    }
+}
+
+pub fn zscore_detector_coroutine(
+    window_size: usize,
+    threshold: f64,
+) -> impl Coroutine<f64, Yield = OutlierReport, Return = ()> {
+    ZScoreDetector::new(window_size, threshold)
+}
+
+pub fn mad_detector_coroutine(
+    window_size: usize,
+    threshold: f64,
+) -> impl Coroutine<f64, Yield = OutlierReport, Return = ()> {
+    MadDetector::new(window_size, threshold)
+}
+
+pub fn iqr_detector_coroutine(
+    window_size: usize,
+    k: f64,
+) -> impl Coroutine<f64, Yield = OutlierReport, Return = ()> {
+    IqrDetector::new(window_size, k)
+}
+
+pub fn cusum_detector_coroutine(
+    params: CusumParams,
+) -> impl Coroutine<f64, Yield = OutlierReport, Return = ()> {
+    CusumDetector::new(params)
+}
+
+#[cfg(test)]
+use crate::{Pipeline, moving_average_coroutine, outlier_detector_coroutine};
+
+// deterministic noise in -6..=6, standard deviation about 3.7
+#[cfg(test)]
+fn noise(i: usize) -> f64 {
+    ((i * 2) % 13) as f64 - 6.0
+}
+
+#[cfg(test)]
+fn flagged(reports: &[OutlierReport]) -> Vec<usize> {
+    reports
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.is_outlier)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[test]
+fn test_zscore_adapts_to_changing_variance() {
+    // quiet until 100 with a spike at 60, then ten times noisier
+    let data: Vec<f64> = (0..200)
+        .map(|i| match i {
+            60 => 5.0,
+            i if i < 100 => 0.1 * noise(i),
+            i => 10.0 * noise(i),
+        })
+        .collect();
+
+    let reports: Vec<OutlierReport> = Pipeline::new(zscore_detector_coroutine(20, 4.0))
+        .run(data.iter().copied())
+        .collect();
+    let flags = flagged(&reports);
+    assert_eq!(flags[0], 60);
+    assert_eq!(reports[60].direction, Direction::Above);
+    // the switch itself is surprising, once the window has adapted nothing is
+    assert!(flags.iter().all(|&i| i == 60 || (100..120).contains(&i)));
+
+    // an absolute threshold keeps firing in the noisy part
+    let absolute = Pipeline::new(moving_average_coroutine(20))
+        .with_input()
+        .then(outlier_detector_coroutine(5.0))
+        .run(data.iter().copied())
+        .skip(120)
+        .flatten()
+        .count();
+    assert!(absolute > 20);
+}
+
+#[test]
+fn test_mad_is_not_masked_by_earlier_outliers() {
+    let mut data: Vec<f64> = (0..60).map(|i| 10.0 + 0.1 * noise(i)).collect();
+    for i in [40, 43, 46, 49] {
+        data[i] = 40.0;
+    }
+
+    let mad: Vec<OutlierReport> = Pipeline::new(mad_detector_coroutine(15, 3.5))
+        .run(data.iter().copied())
+        .collect();
+    assert_eq!(flagged(&mad), vec![40, 43, 46, 49]);
+    assert!((mad[49].baseline - 10.0).abs() < 0.5);
+
+    // the spikes inflate the standard deviation, so the z-score misses the later ones
+    let zscore: Vec<OutlierReport> = Pipeline::new(zscore_detector_coroutine(15, 3.5))
+        .run(data.iter().copied())
+        .collect();
+    assert!(flagged(&zscore).len() < 4);
+}
+
+#[test]
+fn test_iqr_fences() {
+    let mut detector = IqrDetector::new(8, 1.5);
+    for v in 1..=8 {
+        assert!(!detector.push(v as f64).is_outlier);
+    }
+    // quartiles of 1..=8 are 2.75 and 6.25, fences -2.5 and 11.5
+    let inside = detector.push(11.0);
+    assert!(!inside.is_outlier);
+    assert_eq!(inside.baseline, 4.5);
+
+    let mut detector = IqrDetector::new(8, 1.5);
+    for v in 1..=8 {
+        detector.push(v as f64);
+    }
+    let below = detector.push(-20.0);
+    assert!(below.is_outlier);
+    assert_eq!(below.direction, Direction::Below);
+    assert!((below.score - 22.75 / 3.5).abs() < 1e-12);
+}
+
+#[test]
+fn test_cusum_detects_level_shift() {
+    let data: Vec<f64> = (0..150)
+        .map(|i| {
+            if i < 100 {
+                0.1 * noise(i)
+            } else {
+                2.0 + 0.1 * noise(i)
+            }
+        })
+        .collect();
+
+    let reports: Vec<OutlierReport> =
+        Pipeline::new(cusum_detector_coroutine(CusumParams::default()))
+            .run(data.iter().copied())
+            .collect();
+    let flags = flagged(&reports);
+    assert!((100..103).contains(&flags[0]), "detected at {flags:?}");
+    assert_eq!(reports[flags[0]].direction, Direction::Above);
+    // after relearning the new level there are no further alarms
+    assert_eq!(flags.len(), 1);
+}
+
+#[test]
+fn test_window_moments_match_naive() {
+    let data: Vec<f64> = (0..100).map(|i| noise(i) * (i % 7) as f64 + 1e6).collect();
+    let mut moments = WindowMoments::new(9);
+    for i in 0..data.len() {
+        moments.push(data[i]);
+        let window = &data[(i + 1).saturating_sub(9)..=i];
+        let mean = window.iter().sum::<f64>() / window.len() as f64;
+        assert!((moments.mean() - mean).abs() < 1e-6);
+        if window.len() > 1 {
+            let variance =
+                window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (window.len() - 1) as f64;
+            assert!((moments.variance() - variance).abs() < 1e-6 * variance.max(1.0));
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::adapters::{CoroutineExt, Zip};
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+use std::marker::PhantomData;
+use std::ops::{Coroutine, CoroutineState};
+use std::pin::Pin;
+
+/// Chain of coroutine stages, the value yielded by one stage is the resume argument of the next.
+///
+/// A pipeline is a coroutine itself, so it can be resumed by hand, nested into another
+/// pipeline or driven over an iterator of inputs with [`Pipeline::run`].
+/// Like the adapters, the stages have to be `Unpin`.
+pub struct Pipeline<In, C> {
+    stages: C,
+    input: PhantomData<fn(In)>,
+}
+
+impl<In, C: Coroutine<In> + Unpin> Pipeline<In, C> {
+    pub fn new(stage: C) -> Self {
+        Pipeline {
+            stages: stage,
+            input: PhantomData,
+        }
+    }
+
+    /// feeds every value yielded so far into `next`
+    pub fn then<N>(self, next: N) -> Pipeline<In, Then<C, N>>
+    where
+        N: Coroutine<C::Yield> + Unpin,
+    {
+        Pipeline::new(Then {
+            upstream: self.stages,
+            downstream: next,
+        })
+    }
+
+    /// side channel: yields the pipeline input next to the value computed from it,
+    /// e.g. `(value, average)` as the outlier detector expects
+    pub fn with_input(self) -> Pipeline<In, WithInput<C>>
+    where
+        In: Clone,
+    {
+        Pipeline::new(WithInput { inner: self.stages })
+    }
+
+    /// every yielded value goes to both stages, their results are yielded as a pair,
+    /// see [`CoroutineExt::zip`]
+    pub fn fan_out<A, B>(self, first: A, second: B) -> Pipeline<In, Then<C, Zip<A, B>>>
+    where
+        C::Yield: Clone,
+        A: Coroutine<C::Yield> + Unpin,
+        B: Coroutine<C::Yield> + Unpin,
+    {
+        self.then(first.zip(second))
+    }
+
+    /// every yielded value goes to all `stages`, their results are yielded in the same order
+    pub fn fan_out_all<N>(self, stages: Vec<N>) -> Pipeline<In, Then<C, FanOut<N>>>
+    where
+        C::Yield: Clone,
+        N: Coroutine<C::Yield> + Unpin,
+    {
+        self.then(FanOut { stages })
+    }
+
+    /// lazily resumes the pipeline with each input, stops early if a stage completes
+    pub fn run<I>(self, inputs: I) -> PipelineRun<In, C, I::IntoIter>
+    where
+        I: IntoIterator<Item = In>,
+    {
+        PipelineRun {
+            pipeline: self,
+            inputs: inputs.into_iter(),
+            returned: None,
+        }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.stages
+    }
+}
+
+impl<In, C: Coroutine<In> + Unpin> Coroutine<In> for Pipeline<In, C> {
+    type Yield = C::Yield;
+    type Return = C::Return;
+
+    fn resume(self: Pin<&mut Self>, arg: In) -> CoroutineState<C::Yield, C::Return> {
+        Pin::new(&mut self.get_mut().stages).resume(arg)
+    }
+}
+
+/// Which stage of a [`Then`] completed, with its return value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StageEnd<U, D> {
+    Upstream(U),
+    Downstream(D),
+}
+
+pub struct Then<U, D> {
+    upstream: U,
+    downstream: D,
+}
+
+impl<R, U, D> Coroutine<R> for Then<U, D>
+where
+    U: Coroutine<R> + Unpin,
+    D: Coroutine<U::Yield> + Unpin,
+{
+    type Yield = D::Yield;
+    type Return = StageEnd<U::Return, D::Return>;
+
+    fn resume(self: Pin<&mut Self>, arg: R) -> CoroutineState<Self::Yield, Self::Return> {
+        let this = self.get_mut();
+        let value = match Pin::new(&mut this.upstream).resume(arg) {
+            CoroutineState::Yielded(value) => value,
+            CoroutineState::Complete(result) => {
+                return CoroutineState::Complete(StageEnd::Upstream(result));
+            }
+        };
+        match Pin::new(&mut this.downstream).resume(value) {
+            CoroutineState::Yielded(value) => CoroutineState::Yielded(value),
+            CoroutineState::Complete(result) => {
+                CoroutineState::Complete(StageEnd::Downstream(result))
+            }
+        }
+    }
+}
+
+pub struct WithInput<C> {
+    inner: C,
+}
+
+impl<R: Clone, C: Coroutine<R> + Unpin> Coroutine<R> for WithInput<C> {
+    type Yield = (R, C::Yield);
+    type Return = C::Return;
+
+    fn resume(self: Pin<&mut Self>, arg: R) -> CoroutineState<Self::Yield, C::Return> {
+        match Pin::new(&mut self.get_mut().inner).resume(arg.clone()) {
+            CoroutineState::Yielded(value) => CoroutineState::Yielded((arg, value)),
+            CoroutineState::Complete(result) => CoroutineState::Complete(result),
+        }
+    }
+}
+
+pub struct FanOut<C> {
+    stages: Vec<C>,
+}
+
+impl<R: Clone, C: Coroutine<R> + Unpin> Coroutine<R> for FanOut<C> {
+    type Yield = Vec<C::Yield>;
+    /// return value of the first stage that completed, the rest are left as they were
+    type Return = C::Return;
+
+    fn resume(self: Pin<&mut Self>, arg: R) -> CoroutineState<Vec<C::Yield>, C::Return> {
+        let mut values = Vec::with_capacity(self.stages.len());
+        for stage in &mut self.get_mut().stages {
+            match Pin::new(stage).resume(arg.clone()) {
+                CoroutineState::Yielded(value) => values.push(value),
+                CoroutineState::Complete(result) => return CoroutineState::Complete(result),
+            }
+        }
+        CoroutineState::Yielded(values)
+    }
+}
+
+// a pipeline is saved as its stages one after the other, each with its own tag
+impl<In, C: Checkpoint> Checkpoint for Pipeline<In, C> {
+    fn encode(&self, out: &mut Encoder) {
+        self.stages.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        Ok(Pipeline {
+            stages: C::decode(input)?,
+            input: PhantomData,
+        })
+    }
+}
+
+impl<U: Checkpoint, D: Checkpoint> Checkpoint for Then<U, D> {
+    fn encode(&self, out: &mut Encoder) {
+        self.upstream.encode(out);
+        self.downstream.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        Ok(Then {
+            upstream: U::decode(input)?,
+            downstream: D::decode(input)?,
+        })
+    }
+}
+
+impl<C: Checkpoint> Checkpoint for WithInput<C> {
+    fn encode(&self, out: &mut Encoder) {
+        self.inner.encode(out);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        Ok(WithInput {
+            inner: C::decode(input)?,
+        })
+    }
+}
+
+impl<C: Checkpoint> Checkpoint for FanOut<C> {
+    fn encode(&self, out: &mut Encoder) {
+        out.len(self.stages.len());
+        self.stages.iter().for_each(|stage| stage.encode(out));
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        let stages = (0..input.len(4)?)
+            .map(|_| C::decode(input))
+            .collect::<Result<_, _>>()?;
+        Ok(FanOut { stages })
+    }
+}
+
+/// Iterator returned by [`Pipeline::run`].
+pub struct PipelineRun<In, C: Coroutine<In>, I> {
+    pipeline: Pipeline<In, C>,
+    inputs: I,
+    returned: Option<C::Return>,
+}
+
+impl<In, C: Coroutine<In> + Unpin, I> PipelineRun<In, C, I> {
+    /// `Some` once a stage completed, the remaining inputs were not consumed
+    pub fn return_value(&self) -> Option<&C::Return> {
+        self.returned.as_ref()
+    }
+}
+
+impl<In, C, I> Iterator for PipelineRun<In, C, I>
+where
+    C: Coroutine<In> + Unpin,
+    I: Iterator<Item = In>,
+{
+    type Item = C::Yield;
+
+    fn next(&mut self) -> Option<C::Yield> {
+        if self.returned.is_some() {
+            return None;
+        }
+        let input = self.inputs.next()?;
+        match Pin::new(&mut self.pipeline).resume(input) {
+            CoroutineState::Yielded(value) => Some(value),
+            CoroutineState::Complete(result) => {
+                self.returned = Some(result);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::{moving_average_coroutine, outlier_detector_coroutine};
+
+#[test]
+fn test_pipeline_matches_manual_wiring() {
+    let data = vec![1.0, 2.0, 3.0, 4.0, 10.0, 5.0, 6.0, 20.0, 7.0, 8.0];
+
+    let outliers: Vec<f64> = Pipeline::new(moving_average_coroutine(3))
+        .with_input()
+        .then(outlier_detector_coroutine(5.0))
+        .run(data.iter().copied())
+        .flatten()
+        .collect();
+
+    let mut mov_avg = moving_average_coroutine(3);
+    let mut detector = outlier_detector_coroutine(5.0);
+    let mut expected = Vec::new();
+    for &value in &data {
+        let CoroutineState::Yielded(avg) = Pin::new(&mut mov_avg).resume(value) else {
+            unreachable!()
+        };
+        if let CoroutineState::Yielded(Some(o)) = Pin::new(&mut detector).resume((value, avg)) {
+            expected.push(o);
+        }
+    }
+    assert_eq!(outliers, expected);
+    assert_eq!(outliers, vec![20.0]);
+}
+
+#[cfg(test)]
+use crate::adapters::ZipEnd;
+
+#[test]
+fn test_pipeline_fan_out() {
+    let data = [2.0, 4.0, 6.0, 8.0];
+
+    let pairs: Vec<(f64, f64)> = Pipeline::new(moving_average_coroutine(1))
+        .fan_out(moving_average_coroutine(2), moving_average_coroutine(4))
+        .run(data)
+        .collect();
+    assert_eq!(pairs, vec![(2.0, 2.0), (3.0, 3.0), (5.0, 4.0), (7.0, 5.0)]);
+
+    let mut run = Pipeline::new(moving_average_coroutine(1))
+        .fan_out(
+            moving_average_coroutine(2),
+            moving_average_coroutine(1).take(2),
+        )
+        .run(data);
+    assert_eq!(run.by_ref().count(), 2);
+    assert!(matches!(
+        run.return_value(),
+        Some(StageEnd::Downstream(ZipEnd::Second(None)))
+    ));
+
+    let windows: Vec<Vec<f64>> = Pipeline::new(moving_average_coroutine(1))
+        .fan_out_all(vec![
+            moving_average_coroutine(1),
+            moving_average_coroutine(3),
+        ])
+        .run(data)
+        .collect();
+    assert_eq!(windows.last(), Some(&vec![8.0, 6.0]));
+}
+
+#[test]
+fn test_pipeline_stops_when_a_stage_completes() {
+    let mut run = Pipeline::new(moving_average_coroutine(2))
+        .then(moving_average_coroutine(1).take(2))
+        .run((1..=10).map(f64::from));
+    assert_eq!(run.by_ref().collect::<Vec<_>>(), vec![1.0, 1.5]);
+    assert!(matches!(
+        run.return_value(),
+        Some(StageEnd::Downstream(None))
+    ));
+}
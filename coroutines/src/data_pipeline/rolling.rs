@@ -0,0 +1,441 @@
+use super::averages::Ring;
+use super::outliers::WindowMoments;
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::ops::Coroutine;
+
+/// Summary of the current window, yielded by [`rolling_stats_coroutine`] for every sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowStats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// sample variance, 0 for a single value
+    pub variance: f64,
+    pub std_dev: f64,
+    /// `(q, value)` for every quantile requested with [`RollingStats::with_percentiles`]
+    pub percentiles: Vec<(f64, f64)>,
+}
+
+impl WindowStats {
+    pub fn percentile(&self, q: f64) -> Option<f64> {
+        self.percentiles
+            .iter()
+            .find(|(requested, _)| *requested == q)
+            .map(|&(_, value)| value)
+    }
+}
+
+// window values are told apart by their sequence number, so equal values can be removed one by one
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    value: f64,
+    seq: u64,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value
+            .total_cmp(&other.value)
+            .then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+/// Sliding window quantile with two heaps, O(log n) per update.
+///
+/// `lower` holds the smallest values up to the quantile rank, `upper` the rest,
+/// so the quantile and its neighbour for interpolation are always on top.
+/// Evicted values are only marked and dropped once they reach a top, or when
+/// a heap is compacted because more than half of it is stale.
+#[derive(Debug, Clone)]
+struct RollingQuantile {
+    q: f64,
+    lower: BinaryHeap<Entry>,
+    upper: BinaryHeap<Reverse<Entry>>,
+    lower_len: usize,
+    upper_len: usize,
+    removed: HashSet<u64>,
+}
+
+impl RollingQuantile {
+    fn new(q: f64) -> Self {
+        assert!((0.0..=1.0).contains(&q), "quantile must be in [0, 1]");
+        RollingQuantile {
+            q,
+            lower: BinaryHeap::new(),
+            upper: BinaryHeap::new(),
+            lower_len: 0,
+            upper_len: 0,
+            removed: HashSet::new(),
+        }
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        if self.lower.peek().is_none_or(|top| entry <= *top) {
+            self.lower.push(entry);
+            self.lower_len += 1;
+        } else {
+            self.upper.push(Reverse(entry));
+            self.upper_len += 1;
+        }
+        self.rebalance();
+    }
+
+    fn remove(&mut self, entry: Entry) {
+        self.removed.insert(entry.seq);
+        // tops are always live, so comparing with the lower top tells the heap apart
+        if self.lower.peek().is_some_and(|top| entry <= *top) {
+            self.lower_len -= 1;
+        } else {
+            self.upper_len -= 1;
+        }
+        self.prune();
+        self.rebalance();
+        self.compact();
+    }
+
+    fn value(&self) -> f64 {
+        let n = self.lower_len + self.upper_len;
+        let position = self.q * (n - 1) as f64;
+        let low = self
+            .lower
+            .peek()
+            .expect("quantile of an empty window")
+            .value;
+        let fraction = position - position.floor();
+        match self.upper.peek() {
+            Some(Reverse(high)) if fraction > 0.0 => low + (high.value - low) * fraction,
+            _ => low,
+        }
+    }
+
+    fn rebalance(&mut self) {
+        let n = self.lower_len + self.upper_len;
+        // number of values up to and including the floor rank of the quantile
+        let target = if n == 0 {
+            0
+        } else {
+            (self.q * (n - 1) as f64).floor() as usize + 1
+        };
+        while self.lower_len > target {
+            let entry = self.lower.pop().expect("lower heap counts live values");
+            self.upper.push(Reverse(entry));
+            self.lower_len -= 1;
+            self.upper_len += 1;
+            self.prune();
+        }
+        while self.lower_len < target {
+            let Reverse(entry) = self.upper.pop().expect("upper heap counts live values");
+            self.lower.push(entry);
+            self.upper_len -= 1;
+            self.lower_len += 1;
+            self.prune();
+        }
+    }
+
+    fn prune(&mut self) {
+        while let Some(top) = self.lower.peek()
+            && self.removed.remove(&top.seq)
+        {
+            self.lower.pop();
+        }
+        while let Some(Reverse(top)) = self.upper.peek()
+            && self.removed.remove(&top.seq)
+        {
+            self.upper.pop();
+        }
+    }
+
+    // stale values deep inside a heap never reach the top in monotonic streams
+    fn compact(&mut self) {
+        let removed = &mut self.removed;
+        if self.lower.len() > 2 * self.lower_len + 16 {
+            self.lower.retain(|entry| !removed.remove(&entry.seq));
+        }
+        if self.upper.len() > 2 * self.upper_len + 16 {
+            self.upper
+                .retain(|Reverse(entry)| !removed.remove(&entry.seq));
+        }
+    }
+}
+
+/// Sliding window extremum, the deque holds the candidates in window order,
+/// each one better than every later one. O(1) amortized per update.
+#[derive(Debug, Clone)]
+struct MonotonicDeque {
+    candidates: VecDeque<Entry>,
+    // `Greater` keeps the maximum at the front, `Less` the minimum
+    keep: Ordering,
+}
+
+impl MonotonicDeque {
+    fn new(keep: Ordering) -> Self {
+        MonotonicDeque {
+            candidates: VecDeque::new(),
+            keep,
+        }
+    }
+
+    fn push(&mut self, entry: Entry, oldest_seq: u64) {
+        while self
+            .candidates
+            .back()
+            .is_some_and(|last| entry.value.total_cmp(&last.value) != self.keep.reverse())
+        {
+            self.candidates.pop_back();
+        }
+        self.candidates.push_back(entry);
+        while self
+            .candidates
+            .front()
+            .is_some_and(|first| first.seq < oldest_seq)
+        {
+            self.candidates.pop_front();
+        }
+    }
+
+    fn value(&self) -> f64 {
+        self.candidates
+            .front()
+            .expect("extremum of an empty window")
+            .value
+    }
+}
+
+/// State of [`rolling_stats_coroutine`], also usable directly as a pipeline stage.
+#[derive(Debug, Clone)]
+pub struct RollingStats {
+    window: Ring,
+    moments: WindowMoments,
+    min: MonotonicDeque,
+    max: MonotonicDeque,
+    median: RollingQuantile,
+    percentiles: Vec<RollingQuantile>,
+    seq: u64,
+}
+
+impl RollingStats {
+    /// panics if `window_size` is 0 or above 2^24
+    pub fn new(window_size: usize) -> Self {
+        RollingStats {
+            window: Ring::new(window_size),
+            moments: WindowMoments::new(window_size),
+            min: MonotonicDeque::new(Ordering::Less),
+            max: MonotonicDeque::new(Ordering::Greater),
+            median: RollingQuantile::new(0.5),
+            percentiles: Vec::new(),
+            seq: 0,
+        }
+    }
+
+    /// also track these quantiles, each in `[0, 1]`, e.g. `0.99` for p99; panics otherwise
+    pub fn with_percentiles(mut self, quantiles: &[f64]) -> Self {
+        self.percentiles = quantiles.iter().map(|&q| RollingQuantile::new(q)).collect();
+        self
+    }
+
+    pub fn push(&mut self, value: f64) -> WindowStats {
+        let entry = Entry {
+            value,
+            seq: self.seq,
+        };
+        self.seq += 1;
+
+        let evicted = self.window.push(value).map(|value| Entry {
+            value,
+            seq: entry.seq - self.window.len() as u64,
+        });
+        let oldest_seq = self.seq - self.window.len() as u64;
+        self.moments.push(value);
+        self.min.push(entry, oldest_seq);
+        self.max.push(entry, oldest_seq);
+        for quantile in std::iter::once(&mut self.median).chain(&mut self.percentiles) {
+            if let Some(evicted) = evicted {
+                quantile.remove(evicted);
+            }
+            quantile.insert(entry);
+        }
+
+        let variance = self.moments.variance();
+        WindowStats {
+            count: self.window.len(),
+            min: self.min.value(),
+            max: self.max.value(),
+            mean: self.moments.mean(),
+            median: self.median.value(),
+            variance,
+            std_dev: variance.sqrt(),
+            percentiles: self.percentiles.iter().map(|p| (p.q, p.value())).collect(),
+        }
+    }
+}
+
+push_stage!(RollingStats => WindowStats);
+
+impl Checkpoint for RollingStats {
+    // the deques and heaps are rebuilt from the window
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"RST1");
+        self.window.encode(out);
+        self.moments.encode(out);
+        out.u64(self.seq);
+        out.len(self.percentiles.len());
+        self.percentiles.iter().for_each(|p| out.f64(p.q));
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"RST1")?;
+        let window = Ring::decode(input)?;
+        let moments = WindowMoments::decode(input)?;
+        let seq = input.u64()?;
+        let quantiles = (0..input.len(8)?)
+            .map(|_| input.f64())
+            .collect::<Result<Vec<_>, _>>()?;
+        if !quantiles.iter().all(|q| (0.0..=1.0).contains(q)) {
+            return Err(RestoreError::Invalid("quantile out of range"));
+        }
+        let first_seq = seq
+            .checked_sub(window.len() as u64)
+            .ok_or(RestoreError::Invalid("sequence number below window length"))?;
+
+        let values: Vec<f64> = window.iter().collect();
+        let mut stats = RollingStats {
+            window,
+            moments,
+            seq,
+            ..RollingStats::new(1).with_percentiles(&quantiles)
+        };
+        for (value, seq) in values.into_iter().zip(first_seq..) {
+            let entry = Entry { value, seq };
+            stats.min.push(entry, first_seq);
+            stats.max.push(entry, first_seq);
+            for quantile in std::iter::once(&mut stats.median).chain(&mut stats.percentiles) {
+                quantile.insert(entry);
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// Count, min, max, mean, median and variance of the last `window_size` values
+/// per sample; use [`RollingStats::with_percentiles`] as the stage for more quantiles.
+pub fn rolling_stats_coroutine(
+    window_size: usize,
+) -> impl Coroutine<f64, Yield = WindowStats, Return = ()> {
+    RollingStats::new(window_size)
+}
+
+#[cfg(test)]
+use super::outliers::quantile;
+#[cfg(test)]
+use crate::Pipeline;
+
+#[cfg(test)]
+fn naive_stats(window: &[f64], quantiles: &[f64]) -> WindowStats {
+    let mut sorted = window.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let n = window.len() as f64;
+    let mean = window.iter().sum::<f64>() / n;
+    let variance = if window.len() > 1 {
+        window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)
+    } else {
+        0.0
+    };
+    WindowStats {
+        count: window.len(),
+        min: sorted[0],
+        max: sorted[window.len() - 1],
+        mean,
+        median: quantile(&sorted, 0.5),
+        variance,
+        std_dev: variance.sqrt(),
+        percentiles: quantiles
+            .iter()
+            .map(|&q| (q, quantile(&sorted, q)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+fn assert_close(actual: &WindowStats, expected: &WindowStats) {
+    let close = |a: f64, b: f64| (a - b).abs() <= 1e-9 * b.abs().max(1.0);
+    assert_eq!(actual.count, expected.count);
+    assert_eq!((actual.min, actual.max), (expected.min, expected.max));
+    assert_eq!(actual.median, expected.median);
+    assert!(
+        close(actual.mean, expected.mean),
+        "{actual:?} != {expected:?}"
+    );
+    assert!(
+        close(actual.variance, expected.variance),
+        "{actual:?} != {expected:?}"
+    );
+    assert_eq!(actual.percentiles, expected.percentiles);
+}
+
+#[test]
+fn test_rolling_stats_match_naive() {
+    let quantiles = [0.0, 0.1, 0.25, 0.9, 0.99, 1.0];
+    // plenty of repeated values, so equal entries have to be evicted correctly
+    let data: Vec<f64> = (0..400u64)
+        .map(|i| ((i * 7919 + i * i) % 23) as f64 - 11.0)
+        .collect();
+
+    for window_size in [1, 2, 3, 10, 64] {
+        let mut stats = RollingStats::new(window_size).with_percentiles(&quantiles);
+        for i in 0..data.len() {
+            let window = &data[(i + 1).saturating_sub(window_size)..=i];
+            assert_close(&stats.push(data[i]), &naive_stats(window, &quantiles));
+        }
+    }
+}
+
+#[test]
+fn test_rolling_stats_coroutine() {
+    let last = Pipeline::new(rolling_stats_coroutine(3))
+        .run([5.0, 1.0, 4.0, 2.0, 8.0])
+        .last()
+        .unwrap();
+    assert_eq!(
+        (last.count, last.min, last.max, last.median),
+        (3, 2.0, 8.0, 4.0)
+    );
+    assert!((last.variance - 28.0 / 3.0).abs() < 1e-12);
+    assert_eq!(last.percentile(0.5), None);
+
+    let stats = RollingStats::new(4).with_percentiles(&[0.5]).push(1.0);
+    assert_eq!(stats.percentile(0.5), Some(1.0));
+}
+
+#[test]
+fn test_heaps_stay_bounded_on_monotonic_streams() {
+    let mut rising = RollingStats::new(8);
+    let mut falling = RollingStats::new(8);
+    for i in 0..10_000 {
+        rising.push(i as f64);
+        falling.push(-i as f64);
+    }
+    for stats in [&rising, &falling] {
+        let median = &stats.median;
+        assert!(median.lower.len() + median.upper.len() < 64);
+        assert!(median.removed.len() < 64);
+    }
+    assert_eq!(rising.push(10_000.0).median, 9996.5);
+}
@@ -0,0 +1,470 @@
+use super::averages::CompensatedSum;
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+use std::collections::BTreeMap;
+use std::ops::Coroutine;
+use std::time::Duration;
+
+/// How samples are grouped into time windows, all windows are `[start, end)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    /// back to back windows of `size`, aligned to multiples of it
+    Tumbling { size: Duration },
+    /// windows of `size` starting every `slide`, a sample can be in several of them
+    Sliding { size: Duration, slide: Duration },
+    /// runs of samples less than `gap` apart, ending `gap` after the last sample
+    Session { gap: Duration },
+}
+
+impl WindowKind {
+    fn is_valid(&self) -> bool {
+        match *self {
+            WindowKind::Tumbling { size } => !size.is_zero(),
+            WindowKind::Sliding { size, slide } => !size.is_zero() && !slide.is_zero(),
+            WindowKind::Session { gap } => !gap.is_zero(),
+        }
+    }
+}
+
+/// Aggregate of one closed window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeAggregate {
+    pub start: Duration,
+    pub end: Duration,
+    pub count: usize,
+    pub sum: f64,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OpenWindow {
+    end: Duration,
+    count: usize,
+    sum: CompensatedSum,
+    min: f64,
+    max: f64,
+}
+
+impl OpenWindow {
+    fn new(end: Duration) -> Self {
+        OpenWindow {
+            end,
+            count: 0,
+            sum: CompensatedSum::new(),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum.add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: OpenWindow) {
+        self.end = self.end.max(other.end);
+        self.count += other.count;
+        self.sum.add(other.sum.value());
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn close(self, start: Duration) -> TimeAggregate {
+        let sum = self.sum.value();
+        TimeAggregate {
+            start,
+            end: self.end,
+            count: self.count,
+            sum,
+            mean: sum / self.count as f64,
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+/// Event-time windows over `(timestamp, value)` samples.
+///
+/// Samples may arrive out of order: the watermark trails the newest timestamp seen by
+/// the allowed lateness, and a window is only closed and yielded once the watermark
+/// passes its end. A sample whose windows are all closed already is dropped and counted.
+/// With a slide longer than the size, a sample between two sliding windows belongs to
+/// none of them and is ignored without being counted as dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+    kind: WindowKind,
+    allowed_lateness: Duration,
+    // keyed by window start
+    open: BTreeMap<Duration, OpenWindow>,
+    newest: Option<Duration>,
+    dropped: u64,
+}
+
+impl TimeWindow {
+    /// panics if a size, slide or gap is zero
+    pub fn new(kind: WindowKind) -> Self {
+        assert!(kind.is_valid(), "window durations must not be zero");
+        TimeWindow {
+            kind,
+            allowed_lateness: Duration::ZERO,
+            open: BTreeMap::new(),
+            newest: None,
+            dropped: 0,
+        }
+    }
+
+    pub fn tumbling(size: Duration) -> Self {
+        Self::new(WindowKind::Tumbling { size })
+    }
+
+    pub fn sliding(size: Duration, slide: Duration) -> Self {
+        Self::new(WindowKind::Sliding { size, slide })
+    }
+
+    pub fn session(gap: Duration) -> Self {
+        Self::new(WindowKind::Session { gap })
+    }
+
+    /// how far behind the newest timestamp a sample may be and still be counted
+    pub fn with_allowed_lateness(mut self, lateness: Duration) -> Self {
+        self.allowed_lateness = lateness;
+        self
+    }
+
+    /// number of samples dropped for arriving too late
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// adds the sample and yields the windows it closed, oldest first
+    pub fn push(&mut self, (timestamp, value): (Duration, f64)) -> Vec<TimeAggregate> {
+        let watermark = self.watermark();
+        let late = match self.kind {
+            WindowKind::Tumbling { size } => {
+                self.add_to_fixed(timestamp, value, size, size, watermark)
+            }
+            WindowKind::Sliding { size, slide } => {
+                self.add_to_fixed(timestamp, value, size, slide, watermark)
+            }
+            WindowKind::Session { gap } => self.add_to_session(timestamp, value, gap, watermark),
+        };
+        if late {
+            self.dropped += 1;
+            return Vec::new();
+        }
+
+        self.newest = Some(
+            self.newest
+                .map_or(timestamp, |newest| newest.max(timestamp)),
+        );
+        let watermark = self.watermark();
+        let closed: Vec<Duration> = self
+            .open
+            .iter()
+            .filter(|(_, window)| watermark.is_some_and(|w| window.end <= w))
+            .map(|(&start, _)| start)
+            .collect();
+        self.close(closed)
+    }
+
+    /// closes every open window, for the end of the stream
+    pub fn flush(&mut self) -> Vec<TimeAggregate> {
+        let all = self.open.keys().copied().collect();
+        self.close(all)
+    }
+
+    fn watermark(&self) -> Option<Duration> {
+        self.newest
+            .map(|newest| newest.saturating_sub(self.allowed_lateness))
+    }
+
+    fn close(&mut self, starts: Vec<Duration>) -> Vec<TimeAggregate> {
+        starts
+            .into_iter()
+            .filter_map(|start| Some(self.open.remove(&start)?.close(start)))
+            .collect()
+    }
+
+    /// `true` if the sample was too late for every window covering it; a sample no window
+    /// covers, in a sliding gap or in one that would end past `Duration::MAX`, is not late
+    fn add_to_fixed(
+        &mut self,
+        timestamp: Duration,
+        value: f64,
+        size: Duration,
+        slide: Duration,
+        watermark: Option<Duration>,
+    ) -> bool {
+        let mut covered = false;
+        let mut accepted = false;
+        let mut start = Some(align_down(timestamp, slide));
+        // every window start from the latest one back to the first that still covers the sample
+        while let Some(window_start) = start {
+            start = window_start.checked_sub(slide);
+            let Some(end) = window_start.checked_add(size) else {
+                continue;
+            };
+            if end <= timestamp {
+                break;
+            }
+            covered = true;
+            if watermark.is_none_or(|w| end > w) {
+                self.open
+                    .entry(window_start)
+                    .or_insert_with(|| OpenWindow::new(end))
+                    .add(value);
+                accepted = true;
+            }
+        }
+        covered && !accepted
+    }
+
+    /// `true` if the sample was too late, one whose session would end past `Duration::MAX` is ignored
+    fn add_to_session(
+        &mut self,
+        timestamp: Duration,
+        value: f64,
+        gap: Duration,
+        watermark: Option<Duration>,
+    ) -> bool {
+        let Some(end) = timestamp.checked_add(gap) else {
+            return false;
+        };
+        let mut start = timestamp;
+        let mut session = OpenWindow::new(end);
+        session.add(value);
+        let overlapping: Vec<Duration> = self
+            .open
+            .range(..session.end)
+            .filter(|(_, window)| window.end > timestamp)
+            .map(|(&start, _)| start)
+            .collect();
+        // open windows all end after the watermark, so only a new session can be too late
+        if overlapping.is_empty() && watermark.is_some_and(|w| session.end <= w) {
+            return true;
+        }
+        // a sample can bridge the gap between two sessions, merging them
+        for overlapping_start in overlapping {
+            let window = self
+                .open
+                .remove(&overlapping_start)
+                .expect("key was just found");
+            session.merge(window);
+            start = start.min(overlapping_start);
+        }
+        self.open.insert(start, session);
+        false
+    }
+}
+
+fn align_down(timestamp: Duration, step: Duration) -> Duration {
+    let remainder = timestamp.as_nanos() % step.as_nanos();
+    // the remainder is smaller than `step`, which is itself a Duration
+    timestamp - Duration::from_nanos(remainder as u64)
+}
+
+push_stage!(TimeWindow: (Duration, f64) => Vec<TimeAggregate>);
+
+impl Checkpoint for TimeWindow {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"TWN1");
+        match self.kind {
+            WindowKind::Tumbling { size } => {
+                out.u8(0);
+                out.duration(size);
+            }
+            WindowKind::Sliding { size, slide } => {
+                out.u8(1);
+                out.duration(size);
+                out.duration(slide);
+            }
+            WindowKind::Session { gap } => {
+                out.u8(2);
+                out.duration(gap);
+            }
+        }
+        out.duration(self.allowed_lateness);
+        out.len(self.open.len());
+        for (&start, window) in &self.open {
+            out.duration(start);
+            out.duration(window.end);
+            out.len(window.count);
+            window.sum.encode(out);
+            out.f64(window.min);
+            out.f64(window.max);
+        }
+        out.bool(self.newest.is_some());
+        out.duration(self.newest.unwrap_or_default());
+        out.u64(self.dropped);
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"TWN1")?;
+        let kind = match input.u8()? {
+            0 => WindowKind::Tumbling {
+                size: input.duration()?,
+            },
+            1 => WindowKind::Sliding {
+                size: input.duration()?,
+                slide: input.duration()?,
+            },
+            2 => WindowKind::Session {
+                gap: input.duration()?,
+            },
+            _ => return Err(RestoreError::Invalid("unknown window kind")),
+        };
+        if !kind.is_valid() {
+            return Err(RestoreError::Invalid("window durations must not be zero"));
+        }
+        let allowed_lateness = input.duration()?;
+        let mut open = BTreeMap::new();
+        for _ in 0..input.len(72)? {
+            let start = input.duration()?;
+            let window = OpenWindow {
+                end: input.duration()?,
+                count: input.len(0)?,
+                sum: CompensatedSum::decode(input)?,
+                min: input.f64()?,
+                max: input.f64()?,
+            };
+            open.insert(start, window);
+        }
+        let has_newest = input.bool()?;
+        let newest = input.duration()?;
+        Ok(TimeWindow {
+            kind,
+            allowed_lateness,
+            open,
+            newest: has_newest.then_some(newest),
+            dropped: input.u64()?,
+        })
+    }
+}
+
+pub fn tumbling_window_coroutine(
+    size: Duration,
+    allowed_lateness: Duration,
+) -> impl Coroutine<(Duration, f64), Yield = Vec<TimeAggregate>, Return = ()> {
+    TimeWindow::tumbling(size).with_allowed_lateness(allowed_lateness)
+}
+
+pub fn sliding_window_coroutine(
+    size: Duration,
+    slide: Duration,
+    allowed_lateness: Duration,
+) -> impl Coroutine<(Duration, f64), Yield = Vec<TimeAggregate>, Return = ()> {
+    TimeWindow::sliding(size, slide).with_allowed_lateness(allowed_lateness)
+}
+
+pub fn session_window_coroutine(
+    gap: Duration,
+    allowed_lateness: Duration,
+) -> impl Coroutine<(Duration, f64), Yield = Vec<TimeAggregate>, Return = ()> {
+    TimeWindow::session(gap).with_allowed_lateness(allowed_lateness)
+}
+
+#[cfg(test)]
+use crate::Pipeline;
+
+#[cfg(test)]
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+#[cfg(test)]
+fn summary(windows: &[TimeAggregate]) -> Vec<(u64, u64, usize, f64)> {
+    windows
+        .iter()
+        .map(|w| (w.start.as_secs(), w.end.as_secs(), w.count, w.sum))
+        .collect()
+}
+
+#[test]
+fn test_tumbling_windows_with_irregular_samples() {
+    let samples = [(1, 1.0), (4, 2.0), (9, 3.0), (12, 10.0), (31, 5.0)];
+    let closed: Vec<TimeAggregate> =
+        Pipeline::new(tumbling_window_coroutine(secs(10), Duration::ZERO))
+            .run(samples.map(|(t, v)| (secs(t), v)))
+            .flatten()
+            .collect();
+    // the 20..30 window never saw a sample, so there is nothing to report for it
+    assert_eq!(summary(&closed), vec![(0, 10, 3, 6.0), (10, 20, 1, 10.0)]);
+    assert_eq!(
+        (closed[0].mean, closed[0].min, closed[0].max),
+        (2.0, 1.0, 3.0)
+    );
+}
+
+#[test]
+fn test_out_of_order_samples_within_allowed_lateness() {
+    let mut windows = TimeWindow::tumbling(secs(10)).with_allowed_lateness(secs(5));
+    assert!(windows.push((secs(2), 1.0)).is_empty());
+    // watermark is 9, the first window is still open
+    assert!(windows.push((secs(14), 1.0)).is_empty());
+    assert!(windows.push((secs(8), 1.0)).is_empty());
+    let closed = windows.push((secs(16), 1.0));
+    assert_eq!(summary(&closed), vec![(0, 10, 2, 2.0)]);
+
+    // too late now, its window was already reported
+    assert!(windows.push((secs(3), 100.0)).is_empty());
+    assert_eq!(windows.dropped(), 1);
+    assert_eq!(summary(&windows.flush()), vec![(10, 20, 2, 2.0)]);
+}
+
+#[test]
+fn test_sliding_windows_overlap() {
+    let mut windows = TimeWindow::sliding(secs(10), secs(5));
+    let closed: Vec<TimeAggregate> = [(1, 1.0), (6, 2.0), (12, 4.0), (30, 0.0)]
+        .into_iter()
+        .flat_map(|(t, v)| windows.push((secs(t), v)))
+        .collect();
+    assert_eq!(
+        summary(&closed),
+        vec![(0, 10, 2, 3.0), (5, 15, 2, 6.0), (10, 20, 1, 4.0)]
+    );
+    assert_eq!(
+        summary(&windows.flush()),
+        vec![(25, 35, 1, 0.0), (30, 40, 1, 0.0)]
+    );
+}
+
+#[test]
+fn test_sliding_gaps_are_not_counted_as_dropped() {
+    // windows 0..10, 30..40, ..., nothing covers 10..30
+    let mut windows = TimeWindow::sliding(secs(10), secs(30));
+    let mut closed: Vec<TimeAggregate> = [(5, 1.0), (15, 2.0), (31, 3.0)]
+        .into_iter()
+        .flat_map(|(t, v)| windows.push((secs(t), v)))
+        .collect();
+    closed.extend(windows.flush());
+    assert_eq!(summary(&closed), vec![(0, 10, 1, 1.0), (30, 40, 1, 3.0)]);
+    assert_eq!(windows.dropped(), 0);
+
+    // no window ending past Duration::MAX is created, and nothing panics
+    let mut windows = TimeWindow::tumbling(secs(10));
+    assert!(windows.push((Duration::MAX, 1.0)).is_empty());
+    let mut sessions = TimeWindow::session(secs(10));
+    assert!(sessions.push((Duration::MAX, 1.0)).is_empty());
+    assert_eq!((windows.dropped(), sessions.dropped()), (0, 0));
+    assert!(windows.flush().is_empty() && sessions.flush().is_empty());
+}
+
+#[test]
+fn test_session_windows_merge_on_late_bridging_sample() {
+    let mut windows = TimeWindow::session(secs(5)).with_allowed_lateness(secs(10));
+    windows.push((secs(0), 1.0));
+    windows.push((secs(3), 1.0));
+    windows.push((secs(11), 1.0));
+    // arrives late but is less than the gap away from both sessions
+    windows.push((secs(7), 1.0));
+    let closed = windows.push((secs(40), 1.0));
+    assert_eq!(summary(&closed), vec![(0, 16, 4, 4.0)]);
+
+    // its session would have ended at 24, before the watermark of 30
+    assert!(windows.push((secs(19), 1.0)).is_empty());
+    assert_eq!(windows.dropped(), 1);
+    assert_eq!(summary(&windows.flush()), vec![(40, 45, 1, 1.0)]);
+}
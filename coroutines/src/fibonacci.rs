@@ -1,6 +1,9 @@
-use std::ops::Coroutine;
+use num_bigint::BigUint;
+use std::error::Error;
+use std::fmt;
 #[cfg(test)]
 use std::ops::CoroutineState;
+use std::ops::{Add, Coroutine, Mul, Sub};
 #[cfg(test)]
 use std::pin::Pin;
 
@@ -21,6 +24,94 @@ pub fn fibonacci_coroutine(count: usize) -> impl Coroutine<Yield = u64, Return =
     }
 }
 
+/// The term at `index` is the first one that does not fit in a `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FibonacciOverflow {
+    pub index: usize,
+}
+
+impl fmt::Display for FibonacciOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fibonacci term {} does not fit in u64", self.index)
+    }
+}
+
+impl Error for FibonacciOverflow {}
+
+/// Like [`fibonacci_coroutine`], but instead of overflowing past the 93rd term
+/// it completes with an error naming the first term that does not fit.
+pub fn checked_fibonacci_coroutine(
+    count: usize,
+) -> impl Coroutine<Yield = u64, Return = Result<&'static str, FibonacciOverflow>> {
+    #[coroutine]
+    move || {
+        // `None` once a term does not fit, it is only an error if it has to be yielded
+        let mut a: Option<u64> = Some(0);
+        let mut b: Option<u64> = Some(1);
+
+        for index in 0..count {
+            let Some(term) = a else {
+                return Err(FibonacciOverflow { index });
+            };
+            yield term;
+            (a, b) = (b, b.and_then(|b| term.checked_add(b)));
+        }
+
+        Ok("Fibonacci sequence complete")
+    }
+}
+
+/// Exact terms starting at term `start`, without end. Skipping ahead costs
+/// O(log start) big integer multiplications, see [`big_fibonacci_nth`].
+pub fn big_fibonacci_coroutine(start: u64) -> impl Coroutine<Yield = BigUint, Return = ()> {
+    #[coroutine]
+    move || {
+        let (mut a, mut b) = fast_doubling(start, BigUint::ZERO, BigUint::from(1u8));
+        loop {
+            let next = &a + &b;
+            yield std::mem::replace(&mut a, std::mem::replace(&mut b, next));
+        }
+    }
+}
+
+/// Term `n`, or `None` if it does not fit in a `u64` (from term 94 on).
+pub fn fibonacci_nth(n: u64) -> Option<u64> {
+    // F(94) is the first term above u64::MAX and F(94) < u128::MAX, so the u128
+    // intermediate values can not overflow below that
+    if n > 93 {
+        return None;
+    }
+    let (term, _) = fast_doubling(n, 0u128, 1u128);
+    u64::try_from(term).ok()
+}
+
+pub fn big_fibonacci_nth(n: u64) -> BigUint {
+    fast_doubling(n, BigUint::ZERO, BigUint::from(1u8)).0
+}
+
+// (F(n), F(n + 1)) from the identities
+//   F(2k)     = F(k) * (2 F(k+1) - F(k))
+//   F(2k + 1) = F(k)^2 + F(k+1)^2
+// walking the bits of n from the most significant one
+fn fast_doubling<T>(n: u64, zero: T, one: T) -> (T, T)
+where
+    for<'a> &'a T: Add<&'a T, Output = T> + Sub<&'a T, Output = T> + Mul<&'a T, Output = T>,
+{
+    let (mut a, mut b) = (zero, one);
+    for bit in (0..u64::BITS - n.leading_zeros()).rev() {
+        let twice_b = &b + &b;
+        let even = &a * &(&twice_b - &a);
+        let odd = &(&a * &a) + &(&b * &b);
+        (a, b) = if n >> bit & 1 == 0 {
+            (even, odd)
+        } else {
+            let next = &even + &odd;
+            (odd, next)
+        };
+    }
+    (a, b)
+}
+
 #[test]
 fn demo() {
     let mut coro_fib = fibonacci_coroutine(5);
@@ -48,3 +139,48 @@ fn demo() {
     //    |         |         |         |         |         |
     // Yield(0)  Yield(1)  Yield(1)  Yield(2)  Yield(3) Complete("Fib...")
 }
+
+#[cfg(test)]
+use crate::CoroutineExt;
+
+#[test]
+fn test_checked_fibonacci_reports_overflow() {
+    let mut terms = checked_fibonacci_coroutine(200).iter();
+    let last = terms.by_ref().last();
+    assert_eq!(last, Some(12_200_160_415_121_876_738));
+    assert_eq!(
+        terms.into_return_value(),
+        Some(Err(FibonacciOverflow { index: 94 }))
+    );
+
+    // the last term that fits can still be asked for
+    let mut terms = checked_fibonacci_coroutine(94).iter();
+    assert_eq!(terms.by_ref().count(), 94);
+    assert_eq!(
+        terms.into_return_value(),
+        Some(Ok("Fibonacci sequence complete"))
+    );
+}
+
+#[test]
+fn test_nth_matches_sequence() {
+    let sequence: Vec<u64> = checked_fibonacci_coroutine(94).iter().collect();
+    for (n, &term) in sequence.iter().enumerate() {
+        assert_eq!(fibonacci_nth(n as u64), Some(term));
+        assert_eq!(big_fibonacci_nth(n as u64), BigUint::from(term));
+    }
+    assert_eq!(fibonacci_nth(94), None);
+    assert_eq!(fibonacci_nth(u64::MAX), None);
+}
+
+#[test]
+fn test_big_fibonacci_is_exact() {
+    let from_start: Vec<BigUint> = big_fibonacci_coroutine(0).take(120).iter().collect();
+    let skipped: Vec<BigUint> = big_fibonacci_coroutine(100).take(20).iter().collect();
+    assert_eq!(from_start[100..], skipped);
+    assert_eq!(from_start[100].to_string(), "354224848179261915075");
+    assert_eq!(
+        big_fibonacci_nth(300).to_string(),
+        "222232244629420445529739893461909967206666939096499764990979600"
+    );
+}
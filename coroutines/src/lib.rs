@@ -1,11 +1,28 @@
 #![feature(coroutines)]
 #![feature(coroutine_trait)]
-#![feature(stmt_expr_attributes)]
 
+mod adapters;
+mod checkpoint;
 mod data_pipeline;
 mod fibonacci;
 mod state_machine;
 
-pub use data_pipeline::{moving_average_coroutine, outlier_detector_coroutine};
-pub use fibonacci::fibonacci_coroutine;
-pub use state_machine::ai_controller_coroutine;
+pub use adapters::{Chain, CoroutineExt, CoroutineIter, FilterYield, MapYield, Take, Zip, ZipEnd};
+pub use checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+pub use data_pipeline::{
+    AnnotatedRow, AnnotatedWriter, Column, CompensatedSum, CsvOptions, CumulativeAverage,
+    CusumDetector, CusumParams, Direction, ExponentialMovingAverage, FanOut, InputError,
+    InputFormat, IqrDetector, MadDetector, MovingAverage, OutlierReport, OutputFormat, Pipeline,
+    PipelineRun, RollingStats, SeriesReader, StageEnd, Then, ThresholdDetector, TimeAggregate,
+    TimeWindow, Timestamped, Values, WeightedMovingAverage, WindowKind, WindowStats, WithInput,
+    ZScoreDetector, cumulative_average_coroutine, cusum_detector_coroutine, ewma_coroutine,
+    iqr_detector_coroutine, mad_detector_coroutine, moving_average_coroutine,
+    outlier_detector_coroutine, rolling_stats_coroutine, session_window_coroutine,
+    sliding_window_coroutine, tumbling_window_coroutine, weighted_moving_average_coroutine,
+    zscore_detector_coroutine,
+};
+pub use fibonacci::{
+    FibonacciOverflow, big_fibonacci_coroutine, big_fibonacci_nth, checked_fibonacci_coroutine,
+    fibonacci_coroutine, fibonacci_nth,
+};
+pub use state_machine::{AIAction, AiController, GameEvent, ai_controller_coroutine};
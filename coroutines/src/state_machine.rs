@@ -1,11 +1,8 @@
-use std::ops::Coroutine;
-#[cfg(test)]
-use std::ops::CoroutineState;
-#[cfg(test)]
+use crate::checkpoint::{Checkpoint, Decoder, Encoder, RestoreError};
+use std::ops::{Coroutine, CoroutineState};
 use std::pin::Pin;
-use std::vec;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GameEvent {
     EnemySpotted { distance: f32 },
     DamageTaken { amount: i32 },
@@ -15,7 +12,7 @@ pub enum GameEvent {
     Tick,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AIAction {
     Idle,
     Patrol,
@@ -26,74 +23,164 @@ pub enum AIAction {
     Speak(String),
 }
 
-pub fn ai_controller_coroutine() -> impl Coroutine<GameEvent, Yield = Vec<AIAction>, Return = String>
-{
-    #[coroutine]
-    |mut event: GameEvent| {
-        let mut hp = 100;
-        let mut items_collected = 0;
-
-        //this will consume first event and just say halo, no matter what it was
-        event = yield vec![AIAction::Speak("hello moto :)".to_string())];
-
-        loop {
-            match event {
-                GameEvent::Tick => {
-                    if hp < 30 {
-                        event = yield vec![AIAction::Heal];
-                        hp = (hp + 20).min(100);
-                    } else {
-                        event = yield vec![AIAction::Patrol];
-                    }
-                }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Greeting,
+    Running,
+    // the farewell was yielded, the next resume completes
+    Defeated,
+    Finished,
+}
 
-                GameEvent::EnemySpotted { distance } => {
-                    if hp < 30 {
-                        event =
-                            yield vec![AIAction::Speak("woop, c y 👋".to_string()), AIAction::Flee];
-                    } else if distance < 5.0 {
-                        event = yield vec![
-                            AIAction::Speak("gotcha ⚔️".to_string()),
-                            AIAction::Attack {
-                                target_distance: distance,
-                            },
-                        ];
-                    } else {
-                        event = yield vec![AIAction::Patrol];
-                    }
-                }
+/// State of [`ai_controller_coroutine`] as a plain struct instead of a generated coroutine,
+/// so it can be checkpointed and restored mid-game.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AiController {
+    phase: Phase,
+    hp: i32,
+    items_collected: u32,
+}
+
+impl AiController {
+    pub fn new() -> Self {
+        AiController {
+            phase: Phase::Greeting,
+            hp: 100,
+            items_collected: 0,
+        }
+    }
 
-                GameEvent::DamageTaken { amount } => {
-                    hp = (hp - amount).max(0);
+    pub fn hp(&self) -> i32 {
+        self.hp
+    }
 
-                    if hp <= 0 {
-                        yield vec![AIAction::Speak("Defeated! 🪦".to_string())];
-                        return "Bye, Bye!".to_string();
-                    } else {
-                        event = yield vec![AIAction::Speak(format!("Ouch! HP: {}", hp))];
-                    }
+    pub fn items_collected(&self) -> u32 {
+        self.items_collected
+    }
+
+    fn react(&mut self, event: GameEvent) -> Vec<AIAction> {
+        match event {
+            GameEvent::Tick => {
+                if self.hp < 30 {
+                    self.hp = (self.hp + 20).min(100);
+                    vec![AIAction::Heal]
+                } else {
+                    vec![AIAction::Patrol]
                 }
+            }
 
-                // todo change so it returns collection of actions instead of single action
-                GameEvent::ItemFound { item } => {
-                    items_collected += 1;
-                    event = yield vec![
-                        AIAction::PickupItem { item: item.clone() },
-                        AIAction::Speak(format!(
-                            "Found {}! Total items: {}",
-                            item, items_collected
-                        )),
-                    ];
+            GameEvent::EnemySpotted { distance } => {
+                if self.hp < 30 {
+                    vec![AIAction::Speak("woop, c y 👋".to_string()), AIAction::Flee]
+                } else if distance < 5.0 {
+                    vec![
+                        AIAction::Speak("gotcha ⚔️".to_string()),
+                        AIAction::Attack {
+                            target_distance: distance,
+                        },
+                    ]
+                } else {
+                    vec![AIAction::Patrol]
                 }
+            }
+
+            GameEvent::DamageTaken { amount } => {
+                self.hp = (self.hp - amount).max(0);
 
-                GameEvent::AllClear => {
-                    event = yield vec![AIAction::Speak("Area secure".to_string()), AIAction::Idle];
+                if self.hp <= 0 {
+                    self.phase = Phase::Defeated;
+                    vec![AIAction::Speak("Defeated! 🪦".to_string())]
+                } else {
+                    vec![AIAction::Speak(format!("Ouch! HP: {}", self.hp))]
                 }
             }
+
+            GameEvent::ItemFound { item } => {
+                self.items_collected += 1;
+                vec![
+                    AIAction::PickupItem { item: item.clone() },
+                    AIAction::Speak(format!(
+                        "Found {}! Total items: {}",
+                        item, self.items_collected
+                    )),
+                ]
+            }
+
+            GameEvent::AllClear => {
+                vec![AIAction::Speak("Area secure".to_string()), AIAction::Idle]
+            }
+        }
+    }
+}
+
+impl Default for AiController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coroutine<GameEvent> for AiController {
+    type Yield = Vec<AIAction>;
+    type Return = String;
+
+    fn resume(self: Pin<&mut Self>, event: GameEvent) -> CoroutineState<Vec<AIAction>, String> {
+        let this = self.get_mut();
+        match this.phase {
+            // the first event is consumed just to say hello, no matter what it was
+            Phase::Greeting => {
+                this.phase = Phase::Running;
+                CoroutineState::Yielded(vec![AIAction::Speak("hello moto :)".to_string())])
+            }
+            Phase::Running => CoroutineState::Yielded(this.react(event)),
+            Phase::Defeated => {
+                this.phase = Phase::Finished;
+                CoroutineState::Complete("Bye, Bye!".to_string())
+            }
+            Phase::Finished => panic!("`AiController` resumed after completion"),
+        }
+    }
+}
+
+impl Checkpoint for AiController {
+    fn encode(&self, out: &mut Encoder) {
+        out.tag(b"AIC1");
+        out.u8(self.phase as u8);
+        out.i64(i64::from(self.hp));
+        out.u64(u64::from(self.items_collected));
+    }
+
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, RestoreError> {
+        input.tag(b"AIC1")?;
+        let phase = match input.u8()? {
+            0 => Phase::Greeting,
+            1 => Phase::Running,
+            2 => Phase::Defeated,
+            3 => Phase::Finished,
+            _ => return Err(RestoreError::Invalid("unknown controller phase")),
+        };
+        let hp =
+            i32::try_from(input.i64()?).map_err(|_| RestoreError::Invalid("hp out of range"))?;
+        if !(0..=100).contains(&hp) {
+            return Err(RestoreError::Invalid("hp out of range"));
         }
+        // hp only reaches zero on the hit that defeats the controller
+        if matches!(phase, Phase::Defeated | Phase::Finished) != (hp == 0) {
+            return Err(RestoreError::Invalid("hp disagrees with controller phase"));
+        }
+        let items_collected = u32::try_from(input.u64()?)
+            .map_err(|_| RestoreError::Invalid("item count out of range"))?;
+        Ok(AiController {
+            phase,
+            hp,
+            items_collected,
+        })
     }
 }
 
+pub fn ai_controller_coroutine() -> AiController {
+    AiController::new()
+}
+
 #[test]
 fn demo() {
     let events = vec![
@@ -138,3 +225,40 @@ fn demo() {
         }
     }
 }
+
+#[test]
+fn test_checkpoint_restores_mid_game() {
+    let events = vec![
+        GameEvent::Tick,
+        GameEvent::DamageTaken { amount: 80 },
+        GameEvent::ItemFound {
+            item: "Rope".to_string(),
+        },
+        GameEvent::EnemySpotted { distance: 3.0 },
+        GameEvent::Tick,
+        GameEvent::EnemySpotted { distance: 3.0 },
+        GameEvent::DamageTaken { amount: 45 },
+        GameEvent::Tick,
+    ];
+    let run = |ai: &mut AiController, events: &[GameEvent]| {
+        events
+            .iter()
+            .map(|event| match Pin::new(&mut *ai).resume(event.clone()) {
+                CoroutineState::Yielded(actions) => Ok(actions),
+                CoroutineState::Complete(result) => Err(result),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut original = ai_controller_coroutine();
+    let expected = run(&mut original, &events);
+    assert_eq!(expected.last(), Some(&Err("Bye, Bye!".to_string())));
+
+    let mut ai = ai_controller_coroutine();
+    let mut outputs = run(&mut ai, &events[..3]);
+    let bytes = ai.checkpoint();
+    let mut restored = AiController::restore(&bytes).unwrap();
+    assert_eq!((restored.hp(), restored.items_collected()), (20, 1));
+    outputs.extend(run(&mut restored, &events[3..]));
+    assert_eq!(outputs, expected);
+}
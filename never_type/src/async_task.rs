@@ -1,3 +1,8 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
 // it will never finish, i.e. server listening for requests
 pub async fn eternal_listener() -> ! {
     loop {
@@ -16,28 +21,288 @@ pub async fn listener_with_errors() -> Result<!, String> {
     }
 }
 
-// A task that always succeeds but might take a while
-// it is more convention, then explicitly comes from the type
+/// Source of documents for [`guaranteed_fetch_with`].
+pub trait Fetcher {
+    type Error: Debug;
+
+    fn fetch(&self, url: &str) -> impl Future<Output = Result<String, Self::Error>> + Send;
+}
+
+/// Fetches over HTTP(S), treating any non-2xx status as a failed attempt.
+#[derive(Debug, Clone)]
+pub struct HttpFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpFetcher {
+    /// each attempt is limited to 30 seconds, so a hanging server can not stall retries
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .expect("default HTTP client configuration is valid");
+        Self::with_client(client)
+    }
+
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    type Error = reqwest::Error;
+
+    fn fetch(&self, url: &str) -> impl Future<Output = Result<String, reqwest::Error>> + Send {
+        let request = self.client.get(url);
+        async move { request.send().await?.error_for_status()?.text().await }
+    }
+}
+
+/// In-memory fetcher for tests, answers every url with the same body
+/// after failing the configured number of attempts.
+#[derive(Debug)]
+pub struct FakeFetcher {
+    body: String,
+    failures_left: AtomicU32,
+    calls: AtomicU32,
+}
+
+impl FakeFetcher {
+    pub fn new(body: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            failures_left: AtomicU32::new(0),
+            calls: AtomicU32::new(0),
+        }
+    }
+
+    pub fn failing_first(self, failures: u32) -> Self {
+        self.failures_left.store(failures, Ordering::SeqCst);
+        self
+    }
+
+    /// number of fetch attempts made so far
+    pub fn calls(&self) -> u32 {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl Fetcher for FakeFetcher {
+    type Error = String;
+
+    fn fetch(&self, url: &str) -> impl Future<Output = Result<String, String>> + Send {
+        let attempt = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let failed = self
+            .failures_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        let result = if failed {
+            Err(format!("simulated failure #{attempt} for {url}"))
+        } else {
+            Ok(self.body.clone())
+        };
+        async move { result }
+    }
+}
+
+/// Exponential backoff between failed attempts, capped at `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: u32,
+}
+
+impl Backoff {
+    /// delay to wait after the given failed attempt (counting from 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1);
+        let factor = self.multiplier.checked_pow(exponent).unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            multiplier: 2,
+        }
+    }
+}
+
+/// Reported to the progress callback after every failed attempt.
+#[derive(Debug)]
+pub struct FetchProgress<'a, E> {
+    pub url: &'a str,
+    pub attempt: u32,
+    pub error: &'a E,
+    pub retry_in: Duration,
+}
+
+/// A task that always succeeds but might take a while.
+///
+/// The `!` error says this future never resolves to `Err`: it keeps retrying
+/// with [`Backoff::default`] until the fetch succeeds, so if the url is never
+/// reachable it never resolves at all. Bound it with `tokio::time::timeout`
+/// when waiting forever is not acceptable.
 pub async fn guaranteed_fetch(url: &str) -> Result<String, !> {
-    // Retry forever until success
+    guaranteed_fetch_with(&HttpFetcher::new(), url, Backoff::default(), |_| {}).await
+}
+
+/// Same contract as [`guaranteed_fetch`], with a pluggable fetcher and backoff.
+/// Between attempts the task sleeps, so it never busy-loops and only keeps
+/// the state of a single attempt alive.
+pub async fn guaranteed_fetch_with<F, P>(
+    fetcher: &F,
+    url: &str,
+    backoff: Backoff,
+    mut on_progress: P,
+) -> Result<String, !>
+where
+    F: Fetcher,
+    P: FnMut(FetchProgress<'_, F::Error>),
+{
+    let mut attempt: u32 = 0;
     loop {
-        if let Some(result) = try_fetch(url) {
-            return Ok(result);
+        attempt = attempt.saturating_add(1);
+        match fetcher.fetch(url).await {
+            Ok(body) => return Ok(body),
+            Err(error) => {
+                let retry_in = backoff.delay(attempt);
+                on_progress(FetchProgress {
+                    url,
+                    attempt,
+                    error: &error,
+                    retry_in,
+                });
+                tokio::time::sleep(retry_in).await;
+            }
         }
     }
 }
 
-fn try_fetch(_url: &str) -> Option<String> {
-    Some("data".to_string())
+// serves plain HTTP on loopback, answering 503 to the first `failures` requests
+#[cfg(test)]
+async fn flaky_server(
+    failures: usize,
+    body: &'static str,
+) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/data", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+
+            let served = counter.fetch_add(1, Ordering::SeqCst);
+            let (status, content) = if served < failures {
+                ("503 Service Unavailable", "try later")
+            } else {
+                ("200 OK", body)
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{content}",
+                content.len()
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+
+    (url, requests)
 }
 
 #[tokio::test]
 async fn test_async_task() {
+    let fetcher = FakeFetcher::new("data");
     // if it returns it is Ok
-    let Ok(data) = guaranteed_fetch("http://example.com").await;
+    let Ok(data) =
+        guaranteed_fetch_with(&fetcher, "http://example.com", Backoff::default(), |_| {}).await;
     println!("Got data: {}", data);
 
     // if it returns, it's an error
     let Err(e) = listener_with_errors().await;
     println!("Listener failed: {}", e);
 }
+
+#[tokio::test]
+async fn test_fetch_retries_with_backoff_and_progress() {
+    let fetcher = FakeFetcher::new("payload").failing_first(3);
+    let backoff = Backoff {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(3),
+        multiplier: 2,
+    };
+    let mut reported = Vec::new();
+
+    let Ok(data) = guaranteed_fetch_with(&fetcher, "mem://doc", backoff, |p| {
+        reported.push((p.attempt, p.retry_in));
+    })
+    .await;
+
+    assert_eq!(data, "payload");
+    assert_eq!(fetcher.calls(), 4);
+    assert_eq!(
+        reported,
+        vec![
+            (1, Duration::from_millis(1)),
+            (2, Duration::from_millis(2)),
+            (3, Duration::from_millis(3)),
+        ]
+    );
+}
+
+#[test]
+fn test_backoff_delay_is_capped() {
+    let backoff = Backoff::default();
+    assert_eq!(backoff.delay(1), Duration::from_millis(100));
+    assert_eq!(backoff.delay(4), Duration::from_millis(800));
+    assert_eq!(backoff.delay(40), backoff.max);
+    assert_eq!(backoff.delay(u32::MAX), backoff.max);
+}
+
+#[tokio::test]
+async fn test_http_fetch_against_flaky_loopback_server() {
+    let (url, requests) = flaky_server(2, "hello from loopback").await;
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let fetcher = HttpFetcher::with_client(client);
+    let backoff = Backoff {
+        initial: Duration::from_millis(5),
+        max: Duration::from_millis(20),
+        multiplier: 2,
+    };
+    let mut failures = Vec::new();
+
+    let Ok(body) = guaranteed_fetch_with(&fetcher, &url, backoff, |p| {
+        failures.push(p.error.status());
+    })
+    .await;
+
+    assert_eq!(body, "hello from loopback");
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        failures,
+        vec![Some(reqwest::StatusCode::SERVICE_UNAVAILABLE); 2]
+    );
+}
@@ -0,0 +1,196 @@
+use crate::signing::CryptoBackend;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for ! {}
+    impl Sealed for std::convert::Infallible {}
+}
+
+/// Types without values: `!` and its stable stand-in [`Infallible`].
+///
+/// Having one of them proves the code is unreachable, so it converts into anything.
+pub trait Uninhabited: sealed::Sealed {
+    fn absurd<T>(self) -> T;
+}
+
+impl Uninhabited for ! {
+    fn absurd<T>(self) -> T {
+        self
+    }
+}
+
+impl Uninhabited for Infallible {
+    fn absurd<T>(self) -> T {
+        match self {}
+    }
+}
+
+pub fn never_to_infallible(never: !) -> Infallible {
+    never
+}
+
+pub fn infallible_to_never(infallible: Infallible) -> ! {
+    match infallible {}
+}
+
+/// For `Result<T, !>` and `Result<T, Infallible>`, which are always `Ok`.
+///
+/// std has unstable `Result::into_ok` behind `unwrap_infallible`, so calling `.into_ok()`
+/// triggers the `unstable_name_collisions` lint; `.unwrap_infallible()` does the same
+/// without colliding.
+pub trait InfallibleResultExt<T> {
+    fn into_ok(self) -> T;
+
+    fn unwrap_infallible(self) -> T
+    where
+        Self: Sized,
+    {
+        self.into_ok()
+    }
+
+    /// The `From<!>` that std does not provide: the error type becomes whatever the caller needs.
+    fn widen_err<E>(self) -> Result<T, E>;
+}
+
+impl<T, N: Uninhabited> InfallibleResultExt<T> for Result<T, N> {
+    fn into_ok(self) -> T {
+        match self {
+            Ok(value) => value,
+            Err(never) => never.absurd(),
+        }
+    }
+
+    fn widen_err<E>(self) -> Result<T, E> {
+        Ok(self.unwrap_infallible())
+    }
+}
+
+/// For `Result<!, E>` and `Result<Infallible, E>`, which are always `Err`.
+///
+/// `.into_err()` collides with std's unstable `Result::into_err` the same way `into_ok`
+/// does; `.unwrap_err_infallible()` is the non-colliding name.
+pub trait NeverOkResultExt<E> {
+    fn into_err(self) -> E;
+
+    fn unwrap_err_infallible(self) -> E
+    where
+        Self: Sized,
+    {
+        self.into_err()
+    }
+
+    fn widen_ok<T>(self) -> Result<T, E>;
+}
+
+impl<N: Uninhabited, E> NeverOkResultExt<E> for Result<N, E> {
+    fn into_err(self) -> E {
+        match self {
+            Ok(never) => never.absurd(),
+            Err(error) => error,
+        }
+    }
+
+    fn widen_ok<T>(self) -> Result<T, E> {
+        Err(self.unwrap_err_infallible())
+    }
+}
+
+/// Presents an infallible backend with any error types, so it fits APIs written for fallible ones.
+pub struct WidenErrors<B, SE, VE = SE> {
+    inner: B,
+    _errors: PhantomData<fn() -> (SE, VE)>,
+}
+
+impl<B, SE, VE> WidenErrors<B, SE, VE> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            _errors: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B, SE, VE> CryptoBackend for WidenErrors<B, SE, VE>
+where
+    B: CryptoBackend,
+    B::SignError: Uninhabited,
+    B::VerifyError: Uninhabited,
+{
+    type SignError = SE;
+    type VerifyError = VE;
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, SE> {
+        self.inner.sign(data).widen_err()
+    }
+
+    fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, VE> {
+        self.inner.verify(data, sig).widen_err()
+    }
+}
+
+#[cfg(test)]
+use crate::signing::{HsmBackend, HsmError, InMemoryKeys, SoftHsm, sign_document};
+
+#[test]
+fn test_result_extensions() {
+    let always_ok: Result<u32, !> = Ok(7);
+    assert_eq!(InfallibleResultExt::into_ok(always_ok), 7);
+    let std_ok: Result<u32, Infallible> = Ok(8);
+    assert_eq!(std_ok.unwrap_infallible(), 8);
+
+    let always_err: Result<!, String> = Err("stopped".to_string());
+    assert_eq!(always_err.unwrap_err_infallible(), "stopped");
+    let std_err: Result<Infallible, u8> = Err(3);
+    assert_eq!(std_err.widen_ok::<String>(), Err(3));
+
+    // both ways between `!` and `Infallible`
+    let never: Result<u32, !> = Ok(1);
+    let std_ok: Result<u32, Infallible> = never.widen_err();
+    let back: Result<u32, !> = std_ok.map_err(infallible_to_never);
+    assert_eq!(back.map_err(never_to_infallible), Ok(1));
+}
+
+// a pipeline written against a fallible backend, as most real ones are
+#[cfg(test)]
+fn publish<B: CryptoBackend<SignError = HsmError>>(
+    backend: &B,
+    documents: &[&[u8]],
+) -> Result<usize, HsmError> {
+    let mut published = 0;
+    for document in documents {
+        backend.sign(document)?;
+        published += 1;
+    }
+    Ok(published)
+}
+
+#[test]
+fn test_infallible_backends_feed_fallible_pipelines() {
+    let keys = InMemoryKeys::from_seed([30; 32]);
+
+    // `?` on an infallible result inside a fallible function
+    let signed = || -> Result<Vec<u8>, HsmError> { sign_document(&keys, b"doc").widen_err() };
+    assert_eq!(
+        signed().unwrap(),
+        sign_document(&keys, b"doc").unwrap_infallible()
+    );
+
+    let widened: WidenErrors<_, HsmError> = WidenErrors::new(keys);
+    assert_eq!(publish(&widened, &[b"a", b"b"]), Ok(2));
+
+    let hsm = SoftHsm::new();
+    let missing = HsmBackend::connect(
+        &hsm,
+        "HSM-404",
+        crate::SlotId(0),
+        "1234",
+        crate::KeyHandle(1),
+    );
+    assert_eq!(publish(&missing, &[b"a"]), Err(HsmError::DeviceNotFound));
+}
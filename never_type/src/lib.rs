@@ -1,6 +1,27 @@
 #![feature(never_type)]
 mod async_task;
+mod infallible;
 mod signing;
 
-pub use async_task::{eternal_listener, guaranteed_fetch, listener_with_errors};
-pub use signing::{CryptoBackend, HsmBackend, InMemoryKeys, sign_document};
+pub use async_task::{
+    Backoff, FakeFetcher, FetchProgress, Fetcher, HttpFetcher, eternal_listener, guaranteed_fetch,
+    guaranteed_fetch_with, listener_with_errors,
+};
+pub use infallible::{
+    InfallibleResultExt, NeverOkResultExt, Uninhabited, WidenErrors, infallible_to_never,
+    never_to_infallible,
+};
+pub use signing::{
+    Algorithm, Approval, AsyncCryptoBackend, AuditError, AuditLog, AuditRecord, AuditVerifyError,
+    AuditedBackend, BatchVerification, CertError, Certificate, CertificateRequest, ChainHead,
+    CryptoBackend, DecodeError, DerivationError, DerivationPath, DuplicateKeyId, ENVELOPE_VERSION,
+    EnvelopeError, ExtendedKey, FailedAttempt, FailoverError, FailureReason, Fault, FieldTooLong,
+    HARDENED, HmacBackend, HsmBackend, HsmError, InMemoryKeys, IssueError, KdfParams, KeyEntry,
+    KeyHandle, KeyRing, KeyRingError, KeyStore, KeyStoreError, KeyedSignature, MultiSigBackend,
+    MultiSigConfigError, MultiSigSignError, MultiSigVerifyError, Operation, Outcome, RetryDecision,
+    RetryingHsmBackend, RevocationList, Sha256, SignatureBundle, SignedEnvelope, SignerFailure,
+    SignerInfo, SlotId, SoftHsm, SyncAdapter, ThresholdError, Validity, constant_time_eq,
+    derive_keys, hmac_sha256, key_id_for, payload_digest, seal_key, sha256, sign_document,
+    sign_document_async, sign_documents, sign_envelope, unseal_key, validate_chain, verify_ed25519,
+    verify_envelope, verify_log,
+};
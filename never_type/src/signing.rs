@@ -1,12 +1,95 @@
+mod async_backend;
+mod audit;
+mod certificate;
+mod envelope;
+mod failover;
+mod hd;
+mod hmac;
+mod key_ring;
+mod key_store;
+mod multisig;
+mod soft_hsm;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use soft_hsm::Session;
+use std::time::Duration;
+use zeroize::Zeroize;
+
+pub use async_backend::{AsyncCryptoBackend, SyncAdapter, sign_document_async};
+pub use audit::{
+    AuditError, AuditLog, AuditRecord, AuditVerifyError, AuditedBackend, ChainHead, Operation,
+    Outcome, verify_log,
+};
+pub use certificate::{
+    CertError, Certificate, CertificateRequest, IssueError, RevocationList, validate_chain,
+};
+pub use envelope::{
+    Algorithm, DecodeError, ENVELOPE_VERSION, EnvelopeError, FieldTooLong, SignedEnvelope,
+    SignerInfo, payload_digest, sign_envelope, verify_envelope,
+};
+pub use failover::{FailedAttempt, FailoverError, RetryDecision, RetryingHsmBackend};
+pub use hd::{DerivationError, DerivationPath, ExtendedKey, HARDENED, derive_keys};
+pub use hmac::{HmacBackend, Sha256, constant_time_eq, hmac_sha256, sha256};
+pub use key_ring::{DuplicateKeyId, KeyEntry, KeyRing, KeyRingError, KeyedSignature, Validity};
+pub use key_store::{KdfParams, KeyStore, KeyStoreError, seal_key, unseal_key};
+pub use multisig::{
+    Approval, FailureReason, MultiSigBackend, MultiSigConfigError, MultiSigSignError,
+    MultiSigVerifyError, SignatureBundle, SignerFailure, ThresholdError,
+};
+pub use soft_hsm::{Fault, KeyHandle, SlotId, SoftHsm};
+
 pub trait CryptoBackend {
     type SignError;
     type VerifyError;
 
     fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Self::SignError>;
     fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, Self::VerifyError>;
+
+    /// Signs every document or none: the first error aborts the batch.
+    /// Backends with per-call overhead (HSM round trips, remote calls) should override this.
+    fn sign_batch(&self, documents: &[&[u8]]) -> Result<Vec<Vec<u8>>, Self::SignError> {
+        documents
+            .iter()
+            .map(|document| self.sign(document))
+            .collect()
+    }
+
+    /// Verifies each `(data, signature)` pair independently, one bad item does not hide the others.
+    fn verify_batch(&self, items: &[(&[u8], &[u8])]) -> BatchVerification<Self::VerifyError> {
+        BatchVerification {
+            results: items
+                .iter()
+                .map(|(data, sig)| self.verify(data, sig))
+                .collect(),
+        }
+    }
+}
+
+/// Per-item outcome of [`CryptoBackend::verify_batch`], in input order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchVerification<E> {
+    pub results: Vec<Result<bool, E>>,
+}
+
+impl<E> BatchVerification<E> {
+    pub fn all_valid(&self) -> bool {
+        self.results.iter().all(|r| matches!(r, Ok(true)))
+    }
+
+    /// indices of items that are invalid or could not be checked
+    pub fn failed_indices(&self) -> Vec<usize> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !matches!(r, Ok(true)))
+            .map(|(i, _)| i)
+            .collect()
+    }
 }
 
+/// Ed25519 (RFC 8032) keys held in process memory.
 pub struct InMemoryKeys {
+    // the 32 byte Ed25519 seed, the expanded signing key is derived from it on use
     private_key: [u8; 32],
 }
 
@@ -15,46 +98,185 @@ impl CryptoBackend for InMemoryKeys {
     type VerifyError = !; // Verification cannot fail
 
     fn sign(&self, data: &[u8]) -> Result<Vec<u8>, !> {
-        // dummy
         Ok(self.do_sign(data))
     }
 
-    fn verify(&self, _data: &[u8], _sig: &[u8]) -> Result<bool, !> {
-        // dummy
-        Ok(true)
+    // malformed or forged signatures are simply not valid, so this still can not fail
+    fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, !> {
+        Ok(verify_ed25519(&self.public_key(), data, sig))
+    }
+}
+
+/// Checks an Ed25519 signature when only the public key is known.
+pub fn verify_ed25519(public_key: &[u8; 32], data: &[u8], sig: &[u8]) -> bool {
+    let Ok(signature) = Signature::from_slice(sig) else {
+        return false;
+    };
+    let Ok(verifying_key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    verifying_key.verify_strict(data, &signature).is_ok()
+}
+
+impl SignerInfo for InMemoryKeys {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Ed25519
+    }
+
+    /// first 8 bytes of the SHA-256 of the public key, in hex
+    fn key_id(&self) -> String {
+        key_id_for(&self.public_key())
+    }
+}
+
+pub fn key_id_for(public_key: &[u8]) -> String {
+    payload_digest(public_key)[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+// do not leave the seed behind in freed memory
+impl Drop for InMemoryKeys {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+    }
+}
+
+// never print the private key
+impl std::fmt::Debug for InMemoryKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InMemoryKeys")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
     }
 }
 
 impl InMemoryKeys {
-    fn do_sign(&self, _data: &[u8]) -> Vec<u8> {
-        // dummy
-        vec![0x42, 0x24]
+    /// Same seed always gives the same key pair.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self { private_key: seed }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key().verifying_key().to_bytes()
+    }
+
+    fn signing_key(&self) -> SigningKey {
+        SigningKey::from_bytes(&self.private_key)
+    }
+
+    // Ed25519 signing is deterministic, same key and data give the same signature
+    fn do_sign(&self, data: &[u8]) -> Vec<u8> {
+        self.signing_key().sign(data).to_bytes().to_vec()
     }
 }
 
+/// Backend that signs with a key kept inside an HSM device.
 pub struct HsmBackend {
+    hsm: SoftHsm,
     device_id: String,
+    slot: SlotId,
+    pin: String,
+    key: KeyHandle,
+    timeout: Duration,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HsmError {
     DeviceNotFound,
     OperationFailed,
     Timeout,
 }
 
+// never print the PIN
+impl std::fmt::Debug for HsmBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HsmBackend")
+            .field("device_id", &self.device_id)
+            .field("slot", &self.slot)
+            .field("key", &self.key)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HsmBackend {
+    /// nothing is checked until the first operation, as with a real device
+    /// that might be unplugged at any time
+    pub fn connect(
+        hsm: &SoftHsm,
+        device_id: impl Into<String>,
+        slot: SlotId,
+        pin: impl Into<String>,
+        key: KeyHandle,
+    ) -> Self {
+        Self {
+            hsm: hsm.clone(),
+            device_id: device_id.into(),
+            slot,
+            pin: pin.into(),
+            key,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// how long to wait for the device before giving up with [`HsmError::Timeout`]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    fn session(&self) -> Session<'_> {
+        Session {
+            device_id: &self.device_id,
+            slot: self.slot,
+            pin: &self.pin,
+            key: self.key,
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl SignerInfo for HsmBackend {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Ed25519
+    }
+
+    /// the location of the key on the device, reading the public key would need a device round trip
+    fn key_id(&self) -> String {
+        format!("{}/{}/{}", self.device_id, self.slot.0, self.key.0)
+    }
+}
+
 impl CryptoBackend for HsmBackend {
     type SignError = HsmError; // HSM signing can fail
     type VerifyError = HsmError; // HSM verification can fail
 
-    fn sign(&self, _data: &[u8]) -> Result<Vec<u8>, HsmError> {
-        // dummy
-        Ok(vec![0x12, 0x34])
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, HsmError> {
+        self.hsm.sign(&self.session(), data)
     }
 
-    fn verify(&self, _data: &[u8], _sig: &[u8]) -> Result<bool, HsmError> {
-        // dummy
-        Ok(true)
+    fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, HsmError> {
+        self.hsm.verify(&self.session(), data, sig)
+    }
+
+    // one device round trip for the whole batch instead of one per document
+    fn sign_batch(&self, documents: &[&[u8]]) -> Result<Vec<Vec<u8>>, HsmError> {
+        self.hsm.sign_batch(&self.session(), documents)
+    }
+
+    fn verify_batch(&self, items: &[(&[u8], &[u8])]) -> BatchVerification<HsmError> {
+        let results = match self.hsm.verify_batch(&self.session(), items) {
+            Ok(valid) => valid.into_iter().map(Ok).collect(),
+            // the device failed the round trip, so no item could be checked
+            Err(e) => vec![Err(e); items.len()],
+        };
+        BatchVerification { results }
     }
 }
 
@@ -65,19 +287,28 @@ pub fn sign_document<B: CryptoBackend>(
     backend.sign(document)
 }
 
+pub fn sign_documents<B: CryptoBackend>(
+    backend: &B,
+    documents: &[&[u8]],
+) -> Result<Vec<Vec<u8>>, B::SignError> {
+    backend.sign_batch(documents)
+}
+
 #[test]
 fn test_signing() {
-    let in_memory = InMemoryKeys {
-        private_key: [0; 32],
-    };
+    let in_memory = InMemoryKeys::from_seed([0; 32]);
 
     // this can not fail
     let Ok(sig) = sign_document(&in_memory, b"hello");
     println!("In memory signature: {:?}", sig);
 
-    let hsm = HsmBackend {
-        device_id: "HSM-001".to_string(),
-    };
+    let device = SoftHsm::new();
+    device.add_device("HSM-001");
+    let slot = device.init_slot("HSM-001", "1234").unwrap();
+    let key = device
+        .generate_key("HSM-001", slot, "1234", [1; 32])
+        .unwrap();
+    let hsm = HsmBackend::connect(&device, "HSM-001", slot, "1234", key);
 
     // this can fail
     match sign_document(&hsm, b"hello") {
@@ -85,3 +316,247 @@ fn test_signing() {
         Err(e) => println!("HSM error: {:?}", e),
     }
 }
+
+#[cfg(test)]
+pub(crate) fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_in_memory_keys_rfc8032_vectors() {
+    // (secret key, public key, message, signature) from RFC 8032, section 7.1
+    let vectors = [
+        (
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+        (
+            "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        ),
+    ];
+
+    for (secret, public, message, signature) in vectors {
+        let keys = InMemoryKeys::from_seed(from_hex(secret).try_into().unwrap());
+        let message = from_hex(message);
+
+        assert_eq!(keys.public_key().to_vec(), from_hex(public));
+        let Ok(sig) = sign_document(&keys, &message);
+        assert_eq!(sig, from_hex(signature));
+        let Ok(valid) = keys.verify(&message, &sig);
+        assert!(valid);
+    }
+}
+
+#[test]
+fn test_in_memory_keys_reject_tampering_and_forgery() {
+    let keys = InMemoryKeys::from_seed([7; 32]);
+    let other = InMemoryKeys::from_seed([8; 32]);
+    let Ok(sig) = keys.sign(b"release v1.0");
+
+    // deterministic
+    let Ok(again) = keys.sign(b"release v1.0");
+    assert_eq!(sig, again);
+
+    let Ok(tampered_data) = keys.verify(b"release v1.1", &sig);
+    assert!(!tampered_data);
+
+    let mut flipped = sig.clone();
+    flipped[10] ^= 0x01;
+    let Ok(tampered_sig) = keys.verify(b"release v1.0", &flipped);
+    assert!(!tampered_sig);
+
+    let Ok(forged) = other.sign(b"release v1.0");
+    let Ok(wrong_key) = keys.verify(b"release v1.0", &forged);
+    assert!(!wrong_key);
+
+    let Ok(truncated) = keys.verify(b"release v1.0", &sig[..63]);
+    assert!(!truncated);
+}
+
+#[cfg(test)]
+fn soft_hsm_with_key() -> (SoftHsm, SlotId, KeyHandle) {
+    let hsm = SoftHsm::new();
+    hsm.add_device("HSM-001");
+    let slot = hsm.init_slot("HSM-001", "1234").unwrap();
+    let key = hsm.generate_key("HSM-001", slot, "1234", [3; 32]).unwrap();
+    (hsm, slot, key)
+}
+
+#[test]
+fn test_hsm_backend_signs_with_device_key() {
+    let (hsm, slot, key) = soft_hsm_with_key();
+    let backend = HsmBackend::connect(&hsm, "HSM-001", slot, "1234", key);
+
+    let sig = sign_document(&backend, b"hello").unwrap();
+    // the device holds a regular Ed25519 key, so signatures match a software key with the same seed
+    let Ok(expected) = InMemoryKeys::from_seed([3; 32]).sign(b"hello");
+    assert_eq!(sig, expected);
+    assert_eq!(
+        hsm.public_key("HSM-001", slot, key).unwrap(),
+        InMemoryKeys::from_seed([3; 32]).public_key()
+    );
+    assert_eq!(backend.verify(b"hello", &sig), Ok(true));
+    assert_eq!(backend.verify(b"hullo", &sig), Ok(false));
+}
+
+#[test]
+fn test_hsm_backend_error_paths() {
+    let (hsm, slot, key) = soft_hsm_with_key();
+
+    let unknown = HsmBackend::connect(&hsm, "HSM-404", slot, "1234", key);
+    assert_eq!(unknown.sign(b"doc"), Err(HsmError::DeviceNotFound));
+
+    let wrong_pin = HsmBackend::connect(&hsm, "HSM-001", slot, "0000", key);
+    assert_eq!(wrong_pin.sign(b"doc"), Err(HsmError::OperationFailed));
+
+    let wrong_key = HsmBackend::connect(&hsm, "HSM-001", slot, "1234", KeyHandle(99));
+    assert_eq!(
+        wrong_key.verify(b"doc", &[0; 64]),
+        Err(HsmError::OperationFailed)
+    );
+
+    let backend = HsmBackend::connect(&hsm, "HSM-001", slot, "1234", key)
+        .with_timeout(Duration::from_millis(20));
+    hsm.inject_faults("HSM-001", Fault::OperationFailed, 1)
+        .unwrap();
+    hsm.inject_faults("HSM-001", Fault::Timeout, 1).unwrap();
+    assert_eq!(backend.sign(b"doc"), Err(HsmError::OperationFailed));
+    assert_eq!(backend.sign(b"doc"), Err(HsmError::Timeout));
+    assert!(backend.sign(b"doc").is_ok());
+
+    hsm.set_latency("HSM-001", Duration::from_millis(50))
+        .unwrap();
+    assert_eq!(backend.sign(b"doc"), Err(HsmError::Timeout));
+    hsm.set_latency("HSM-001", Duration::from_millis(1))
+        .unwrap();
+    assert!(backend.sign(b"doc").is_ok());
+
+    assert!(hsm.remove_device("HSM-001"));
+    assert_eq!(backend.sign(b"doc"), Err(HsmError::DeviceNotFound));
+}
+
+#[tokio::test]
+async fn test_async_signing() {
+    // infallible sync backends stay infallible behind the adapter
+    let in_memory = SyncAdapter::new(InMemoryKeys::from_seed([5; 32]));
+    let Ok(sig) = sign_document_async(&in_memory, b"hello").await;
+    let Ok(valid) = AsyncCryptoBackend::verify(&in_memory, b"hello", &sig).await;
+    assert!(valid);
+
+    let (device, slot, key) = soft_hsm_with_key();
+    device.remove_device("HSM-001");
+    let hsm = SyncAdapter::new(HsmBackend::connect(&device, "HSM-001", slot, "1234", key));
+    assert_eq!(
+        sign_document_async(&hsm, b"hello").await,
+        Err(HsmError::DeviceNotFound)
+    );
+}
+
+#[cfg(test)]
+struct RemoteKms {
+    keys: InMemoryKeys,
+    reachable: bool,
+}
+
+#[cfg(test)]
+impl AsyncCryptoBackend for RemoteKms {
+    type SignError = String;
+    type VerifyError = String;
+
+    async fn sign(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        if !self.reachable {
+            return Err("kms unreachable".to_string());
+        }
+        let Ok(sig) = self.keys.sign(data);
+        Ok(sig)
+    }
+
+    async fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, String> {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let Ok(valid) = self.keys.verify(data, sig);
+        Ok(valid)
+    }
+}
+
+#[tokio::test]
+async fn test_native_async_backend() {
+    let kms = RemoteKms {
+        keys: InMemoryKeys::from_seed([6; 32]),
+        reachable: true,
+    };
+    let sig = sign_document_async(&kms, b"hello").await.unwrap();
+    assert_eq!(kms.verify(b"hello", &sig).await, Ok(true));
+
+    let down = RemoteKms {
+        reachable: false,
+        ..kms
+    };
+    assert_eq!(
+        sign_document_async(&down, b"hello").await,
+        Err("kms unreachable".to_string())
+    );
+}
+
+#[test]
+fn test_batch_signing_and_verification() {
+    let keys = InMemoryKeys::from_seed([4; 32]);
+    let documents: [&[u8]; 3] = [b"one", b"two", b"three"];
+
+    // infallible backends keep infallible batches
+    let Ok(sigs) = sign_documents(&keys, &documents);
+    assert_eq!(sigs.len(), 3);
+
+    let report = keys.verify_batch(&[
+        (documents[0], &sigs[0]),
+        (documents[1], &sigs[2]),
+        (documents[2], &sigs[2]),
+        (b"four", &sigs[0]),
+    ]);
+    assert!(!report.all_valid());
+    assert_eq!(report.failed_indices(), vec![1, 3]);
+}
+
+#[test]
+fn test_hsm_batch_is_one_round_trip() {
+    let (device, slot, key) = soft_hsm_with_key();
+    let hsm = HsmBackend::connect(&device, "HSM-001", slot, "1234", key);
+    let documents: Vec<Vec<u8>> = (0..100).map(|i| format!("doc-{i}").into_bytes()).collect();
+    let documents: Vec<&[u8]> = documents.iter().map(Vec::as_slice).collect();
+
+    let sigs = sign_documents(&hsm, &documents).unwrap();
+    assert_eq!(device.round_trips("HSM-001"), Ok(1));
+    let Ok(expected) = InMemoryKeys::from_seed([3; 32]).sign(documents[42]);
+    assert_eq!(sigs[42], expected);
+
+    let mut items: Vec<(&[u8], &[u8])> = documents
+        .iter()
+        .copied()
+        .zip(sigs.iter().map(Vec::as_slice))
+        .collect();
+    items[7].1 = &sigs[8];
+    let report = hsm.verify_batch(&items);
+    assert_eq!(device.round_trips("HSM-001"), Ok(2));
+    assert_eq!(report.failed_indices(), vec![7]);
+
+    device
+        .inject_faults("HSM-001", Fault::OperationFailed, 1)
+        .unwrap();
+    let report = hsm.verify_batch(&items[..2]);
+    assert_eq!(report.results, vec![Err(HsmError::OperationFailed); 2]);
+    assert_eq!(sign_documents(&hsm, &documents), Ok(sigs));
+}
@@ -0,0 +1,55 @@
+use super::CryptoBackend;
+use std::future::Future;
+
+/// Async counterpart of [`CryptoBackend`] for I/O bound backends such as remote KMS.
+///
+/// Errors are associated types for the same reason as in the sync trait:
+/// a backend that can not fail uses `!` and callers need no error handling.
+pub trait AsyncCryptoBackend {
+    type SignError;
+    type VerifyError;
+
+    fn sign(&self, data: &[u8]) -> impl Future<Output = Result<Vec<u8>, Self::SignError>> + Send;
+    fn verify(
+        &self,
+        data: &[u8],
+        sig: &[u8],
+    ) -> impl Future<Output = Result<bool, Self::VerifyError>> + Send;
+}
+
+/// Makes any sync [`CryptoBackend`] usable where an [`AsyncCryptoBackend`] is expected.
+///
+/// The sync call runs inline when the future is polled, so slow backends
+/// still block the executor thread for the duration of the call.
+#[derive(Debug)]
+pub struct SyncAdapter<B>(pub B);
+
+impl<B> SyncAdapter<B> {
+    pub fn new(backend: B) -> Self {
+        Self(backend)
+    }
+
+    pub fn into_inner(self) -> B {
+        self.0
+    }
+}
+
+impl<B: CryptoBackend + Sync> AsyncCryptoBackend for SyncAdapter<B> {
+    type SignError = B::SignError;
+    type VerifyError = B::VerifyError;
+
+    async fn sign(&self, data: &[u8]) -> Result<Vec<u8>, B::SignError> {
+        self.0.sign(data)
+    }
+
+    async fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, B::VerifyError> {
+        self.0.verify(data, sig)
+    }
+}
+
+pub async fn sign_document_async<B: AsyncCryptoBackend>(
+    backend: &B,
+    document: &[u8],
+) -> Result<Vec<u8>, B::SignError> {
+    backend.sign(document).await
+}
@@ -0,0 +1,545 @@
+use super::{CryptoBackend, SignerInfo, payload_digest};
+use std::fmt::{self, Debug, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GENESIS: [u8; 32] = [0; 32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Sign,
+    Verify,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Signed,
+    Valid,
+    Invalid,
+    Failed(String),
+}
+
+/// One line of the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub seq: u64,
+    /// milliseconds since the unix epoch
+    pub timestamp: u64,
+    pub operation: Operation,
+    pub key_id: String,
+    pub document_digest: [u8; 32],
+    pub outcome: Outcome,
+    pub prev_hash: [u8; 32],
+}
+
+/// Sequence number and hash of the last record; keep a copy outside the log to detect truncation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainHead {
+    pub seq: u64,
+    pub hash: [u8; 32],
+}
+
+impl ChainHead {
+    const EMPTY: ChainHead = ChainHead {
+        seq: 0,
+        hash: GENESIS,
+    };
+}
+
+#[derive(Debug)]
+pub enum AuditVerifyError {
+    Io(io::Error),
+    /// a line can not be parsed, e.g. a torn write
+    Malformed {
+        line: usize,
+    },
+    /// a record was modified, removed or reordered
+    BrokenChain {
+        line: usize,
+    },
+    /// the log ends before the externally kept head
+    Truncated {
+        expected: ChainHead,
+        found: ChainHead,
+    },
+}
+
+impl From<io::Error> for AuditVerifyError {
+    fn from(e: io::Error) -> Self {
+        AuditVerifyError::Io(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum AuditError<E> {
+    Backend(E),
+    /// the operation happened but could not be recorded, so its result is withheld
+    Log(io::Error),
+}
+
+impl AuditRecord {
+    fn hash(&self) -> [u8; 32] {
+        payload_digest(self.body().as_bytes())
+    }
+
+    // every field except the record's own hash, `|` separated
+    fn body(&self) -> String {
+        let operation = match self.operation {
+            Operation::Sign => "sign",
+            Operation::Verify => "verify",
+        };
+        let outcome = match &self.outcome {
+            Outcome::Signed => "signed".to_string(),
+            Outcome::Valid => "valid".to_string(),
+            Outcome::Invalid => "invalid".to_string(),
+            Outcome::Failed(reason) => format!("failed:{}", escape(reason)),
+        };
+        format!(
+            "{}|{}|{}|{}|{}|{}|{}",
+            self.seq,
+            self.timestamp,
+            operation,
+            escape(&self.key_id),
+            hex(&self.document_digest),
+            outcome,
+            hex(&self.prev_hash)
+        )
+    }
+
+    fn parse(line: &str) -> Option<(AuditRecord, [u8; 32])> {
+        let fields: Vec<&str> = line.split('|').collect();
+        let [
+            seq,
+            timestamp,
+            operation,
+            key_id,
+            digest,
+            outcome,
+            prev_hash,
+            hash,
+        ] = fields[..]
+        else {
+            return None;
+        };
+        let record = AuditRecord {
+            seq: seq.parse().ok()?,
+            timestamp: timestamp.parse().ok()?,
+            operation: match operation {
+                "sign" => Operation::Sign,
+                "verify" => Operation::Verify,
+                _ => return None,
+            },
+            key_id: unescape(key_id)?,
+            document_digest: unhex(digest)?,
+            outcome: match outcome {
+                "signed" => Outcome::Signed,
+                "valid" => Outcome::Valid,
+                "invalid" => Outcome::Invalid,
+                _ => Outcome::Failed(unescape(outcome.strip_prefix("failed:")?)?),
+            },
+            prev_hash: unhex(prev_hash)?,
+        };
+        Some((record, unhex(hash)?))
+    }
+}
+
+/// Append-only file of hash-chained records: each record includes the hash of the one before it.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: File,
+    head: ChainHead,
+}
+
+impl AuditLog {
+    /// Creates the file or continues an existing log, which must verify first.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AuditVerifyError> {
+        let path = path.as_ref();
+        let head = if path.exists() {
+            verify_log(path, None)?
+        } else {
+            ChainHead::EMPTY
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file, head })
+    }
+
+    pub fn head(&self) -> ChainHead {
+        self.head
+    }
+
+    /// Written and synced to disk before returning.
+    pub fn append(
+        &mut self,
+        operation: Operation,
+        key_id: &str,
+        document: &[u8],
+        outcome: Outcome,
+    ) -> io::Result<AuditRecord> {
+        let record = AuditRecord {
+            seq: self.head.seq + 1,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            operation,
+            key_id: key_id.to_string(),
+            document_digest: payload_digest(document),
+            outcome,
+            prev_hash: self.head.hash,
+        };
+        let hash = record.hash();
+        writeln!(self.file, "{}|{}", record.body(), hex(&hash))?;
+        self.file.sync_data()?;
+        self.head = ChainHead {
+            seq: record.seq,
+            hash,
+        };
+        Ok(record)
+    }
+}
+
+/// Walks the whole chain and returns its head.
+///
+/// Edits, deletions and reordering anywhere in the file break the chain. Cutting records off
+/// the end leaves a valid shorter chain, which is only detected when `expected` (a head kept
+/// elsewhere) is given. The record at `expected.seq` must also still have the saved hash,
+/// so a log rewritten and re-chained from an earlier record is caught even after it grew.
+pub fn verify_log(
+    path: impl AsRef<Path>,
+    expected: Option<ChainHead>,
+) -> Result<ChainHead, AuditVerifyError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut head = ChainHead::EMPTY;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line?;
+        let (record, hash) =
+            AuditRecord::parse(&line).ok_or(AuditVerifyError::Malformed { line: line_no })?;
+        if record.seq != head.seq + 1 || record.prev_hash != head.hash || record.hash() != hash {
+            return Err(AuditVerifyError::BrokenChain { line: line_no });
+        }
+        if expected.is_some_and(|e| e.seq == record.seq && e.hash != hash) {
+            return Err(AuditVerifyError::BrokenChain { line: line_no });
+        }
+        head = ChainHead {
+            seq: record.seq,
+            hash,
+        };
+    }
+
+    match expected {
+        Some(expected) if expected.seq > head.seq => Err(AuditVerifyError::Truncated {
+            expected,
+            found: head,
+        }),
+        Some(expected) if expected.seq == 0 && expected.hash != GENESIS => {
+            Err(AuditVerifyError::BrokenChain { line: 0 })
+        }
+        _ => Ok(head),
+    }
+}
+
+/// Records every sign and verify call of the wrapped backend in an [`AuditLog`].
+#[derive(Debug)]
+pub struct AuditedBackend<B> {
+    inner: B,
+    log: Mutex<AuditLog>,
+}
+
+impl<B> AuditedBackend<B> {
+    pub fn new(inner: B, log: AuditLog) -> Self {
+        Self {
+            inner,
+            log: Mutex::new(log),
+        }
+    }
+
+    pub fn head(&self) -> ChainHead {
+        self.log().head()
+    }
+
+    pub fn into_inner(self) -> (B, AuditLog) {
+        let log = self.log.into_inner().unwrap_or_else(|p| p.into_inner());
+        (self.inner, log)
+    }
+
+    fn log(&self) -> std::sync::MutexGuard<'_, AuditLog> {
+        self.log.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl<B> CryptoBackend for AuditedBackend<B>
+where
+    B: CryptoBackend + SignerInfo,
+    B::SignError: Debug,
+    B::VerifyError: Debug,
+{
+    type SignError = AuditError<B::SignError>;
+    type VerifyError = AuditError<B::VerifyError>;
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Self::SignError> {
+        let result = self.inner.sign(data);
+        let outcome = match &result {
+            Ok(_) => Outcome::Signed,
+            Err(e) => Outcome::Failed(format!("{e:?}")),
+        };
+        self.log()
+            .append(Operation::Sign, &self.inner.key_id(), data, outcome)
+            .map_err(AuditError::Log)?;
+        result.map_err(AuditError::Backend)
+    }
+
+    fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, Self::VerifyError> {
+        let result = self.inner.verify(data, sig);
+        let outcome = match &result {
+            Ok(true) => Outcome::Valid,
+            Ok(false) => Outcome::Invalid,
+            Err(e) => Outcome::Failed(format!("{e:?}")),
+        };
+        self.log()
+            .append(Operation::Verify, &self.inner.key_id(), data, outcome)
+            .map_err(AuditError::Log)?;
+        result.map_err(AuditError::Backend)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+fn unhex(text: &str) -> Option<[u8; 32]> {
+    if text.len() != 64 {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+// keeps `|` and newlines out of free-text fields
+fn escape(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('|', "%7C")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('%') {
+        out.push_str(&rest[..pos]);
+        let code = u8::from_str_radix(rest.get(pos + 1..pos + 3)?, 16).ok()?;
+        out.push(code as char);
+        rest = &rest[pos + 3..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+impl fmt::Display for AuditVerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditVerifyError::Io(e) => write!(f, "audit log I/O error: {e}"),
+            AuditVerifyError::Malformed { line } => {
+                write!(f, "malformed audit record at line {line}")
+            }
+            AuditVerifyError::BrokenChain { line } => {
+                write!(f, "audit hash chain broken at line {line}")
+            }
+            AuditVerifyError::Truncated { expected, found } => write!(
+                f,
+                "audit log truncated: expected {} records, found {}",
+                expected.seq, found.seq
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AuditVerifyError {}
+
+#[cfg(test)]
+use super::{HsmBackend, HsmError, InMemoryKeys};
+
+#[cfg(test)]
+fn temp_log(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "never_type_audit_{}_{name}.log",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_audited_backend_records_operations() {
+    let path = temp_log("records");
+    let audited = AuditedBackend::new(
+        InMemoryKeys::from_seed([13; 32]),
+        AuditLog::open(&path).unwrap(),
+    );
+
+    let sig = audited.sign(b"invoice-1").unwrap();
+    assert!(audited.verify(b"invoice-1", &sig).unwrap());
+    assert!(!audited.verify(b"invoice-2", &sig).unwrap());
+    let head = audited.head();
+    assert_eq!(head.seq, 3);
+    assert_eq!(verify_log(&path, Some(head)).unwrap(), head);
+
+    let lines: Vec<(AuditRecord, [u8; 32])> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|l| AuditRecord::parse(l).unwrap())
+        .collect();
+    let outcomes: Vec<Outcome> = lines.iter().map(|(r, _)| r.outcome.clone()).collect();
+    assert_eq!(
+        outcomes,
+        vec![Outcome::Signed, Outcome::Valid, Outcome::Invalid]
+    );
+    assert_eq!(lines[0].0.document_digest, payload_digest(b"invoice-1"));
+    assert_eq!(
+        lines[0].0.key_id,
+        InMemoryKeys::from_seed([13; 32]).key_id()
+    );
+
+    // reopening continues the same chain
+    let (keys, log) = audited.into_inner();
+    drop(log);
+    let audited = AuditedBackend::new(keys, AuditLog::open(&path).unwrap());
+    audited.sign(b"invoice-3").unwrap();
+    assert_eq!(verify_log(&path, Some(head)).unwrap().seq, 4);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_audited_backend_records_failures() {
+    let path = temp_log("failures");
+    let hsm = super::SoftHsm::new();
+    let audited = AuditedBackend::new(
+        HsmBackend::connect(
+            &hsm,
+            "HSM-404",
+            super::SlotId(0),
+            "1234",
+            super::KeyHandle(1),
+        ),
+        AuditLog::open(&path).unwrap(),
+    );
+
+    let Err(AuditError::Backend(HsmError::DeviceNotFound)) = audited.sign(b"doc") else {
+        panic!("the device does not exist");
+    };
+    let text = std::fs::read_to_string(&path).unwrap();
+    let (record, _) = AuditRecord::parse(text.trim_end()).unwrap();
+    assert_eq!(
+        record.outcome,
+        Outcome::Failed("DeviceNotFound".to_string())
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_verify_log_detects_tampering() {
+    let path = temp_log("tampering");
+    let audited = AuditedBackend::new(
+        InMemoryKeys::from_seed([14; 32]),
+        AuditLog::open(&path).unwrap(),
+    );
+    for i in 0..4 {
+        audited.sign(format!("doc-{i}").as_bytes()).unwrap();
+    }
+    let head = audited.head();
+    let original = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = original.lines().collect();
+    let write = |lines: &[&str]| {
+        let text: String = lines.iter().map(|l| format!("{l}\n")).collect();
+        std::fs::write(&path, text).unwrap();
+    };
+
+    // modified outcome
+    let forged = lines[1].replace("|signed|", "|invalid|");
+    write(&[lines[0], &forged, lines[2], lines[3]]);
+    assert!(matches!(
+        verify_log(&path, None),
+        Err(AuditVerifyError::BrokenChain { line: 2 })
+    ));
+
+    // removed record in the middle
+    write(&[lines[0], lines[2], lines[3]]);
+    assert!(matches!(
+        verify_log(&path, None),
+        Err(AuditVerifyError::BrokenChain { line: 2 })
+    ));
+
+    // cut off at the end: consistent on its own, caught by the saved head
+    write(&lines[..3]);
+    assert!(verify_log(&path, None).is_ok());
+    assert!(matches!(
+        verify_log(&path, Some(head)),
+        Err(AuditVerifyError::Truncated {
+            found: ChainHead { seq: 3, .. },
+            ..
+        })
+    ));
+
+    // torn last write
+    write(&[lines[0], lines[1], &lines[2][..20]]);
+    assert!(matches!(
+        verify_log(&path, None),
+        Err(AuditVerifyError::Malformed { line: 3 })
+    ));
+    assert!(AuditLog::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_verify_log_detects_rechained_history() {
+    let path = temp_log("rechained");
+    let keys = InMemoryKeys::from_seed([15; 32]);
+    let audited = AuditedBackend::new(keys, AuditLog::open(&path).unwrap());
+    for i in 0..3 {
+        audited.sign(format!("doc-{i}").as_bytes()).unwrap();
+    }
+    let saved = audited.head();
+    let (keys, log) = audited.into_inner();
+    drop(log);
+
+    // rewrite the first record and recompute every hash after it
+    let mut prev_hash = GENESIS;
+    let mut text = String::new();
+    for line in std::fs::read_to_string(&path).unwrap().lines() {
+        let (mut record, _) = AuditRecord::parse(line).unwrap();
+        if record.seq == 1 {
+            record.outcome = Outcome::Invalid;
+        }
+        record.prev_hash = prev_hash;
+        prev_hash = record.hash();
+        text.push_str(&format!("{}|{}\n", record.body(), hex(&prev_hash)));
+    }
+    std::fs::write(&path, text).unwrap();
+
+    // one more record, so the saved head is no longer the last one
+    let audited = AuditedBackend::new(keys, AuditLog::open(&path).unwrap());
+    audited.sign(b"doc-3").unwrap();
+    assert_eq!(verify_log(&path, None).unwrap().seq, 4);
+    assert!(matches!(
+        verify_log(&path, Some(saved)),
+        Err(AuditVerifyError::BrokenChain { line: 3 })
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_audit_record_escaping() {
+    assert_eq!(escape("a|b\n%"), "a%7Cb%0A%25");
+    assert_eq!(unescape(&escape("a|b\n%c")).unwrap(), "a|b\n%c");
+    assert_eq!(unescape("%zz"), None);
+}
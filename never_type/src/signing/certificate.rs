@@ -0,0 +1,510 @@
+use super::envelope::{FieldTooLong, Reader, u16_len};
+use super::{Algorithm, CryptoBackend, SignerInfo, verify_ed25519};
+use std::collections::HashSet;
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"NTC1";
+// longest issuer chain followed, also stops issuer loops
+const MAX_CHAIN_LEN: usize = 8;
+
+/// Ties an Ed25519 public key to a subject name, signed by the issuer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    pub serial: u64,
+    pub subject: String,
+    pub issuer: String,
+    pub public_key: [u8; 32],
+    /// unix seconds, inclusive
+    pub not_before: u64,
+    /// unix seconds, exclusive
+    pub not_after: u64,
+    /// whether the key may issue certificates
+    pub is_ca: bool,
+    pub signature: Vec<u8>,
+}
+
+/// Everything of a certificate except who issues it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateRequest {
+    pub serial: u64,
+    pub subject: String,
+    pub public_key: [u8; 32],
+    pub not_before: u64,
+    pub not_after: u64,
+    pub is_ca: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertError {
+    NotYetValid {
+        subject: String,
+        not_before: u64,
+    },
+    Expired {
+        subject: String,
+        not_after: u64,
+    },
+    Revoked {
+        subject: String,
+        serial: u64,
+    },
+    /// no intermediate or trust anchor named as issuer verifies the signature
+    UnknownIssuer {
+        subject: String,
+        issuer: String,
+    },
+    /// the issuer certificate may not issue certificates
+    NotACa {
+        subject: String,
+    },
+    ChainTooLong,
+    Malformed,
+}
+
+impl fmt::Display for CertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertError::NotYetValid {
+                subject,
+                not_before,
+            } => {
+                write!(
+                    f,
+                    "certificate of {subject} is not valid before {not_before}"
+                )
+            }
+            CertError::Expired { subject, not_after } => {
+                write!(f, "certificate of {subject} expired at {not_after}")
+            }
+            CertError::Revoked { subject, serial } => {
+                write!(f, "certificate {serial} of {subject} is revoked")
+            }
+            CertError::UnknownIssuer { subject, issuer } => {
+                write!(f, "no trusted issuer {issuer} for {subject}")
+            }
+            CertError::NotACa { subject } => write!(f, "{subject} may not issue certificates"),
+            CertError::ChainTooLong => write!(f, "certificate chain too long"),
+            CertError::Malformed => write!(f, "malformed certificate"),
+        }
+    }
+}
+
+impl std::error::Error for CertError {}
+
+/// Why a certificate could not be issued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueError<E> {
+    /// chains are validated with Ed25519 only, any other signature would never verify
+    UnsupportedAlgorithm(Algorithm),
+    FieldTooLong(FieldTooLong),
+    Backend(E),
+}
+
+impl Certificate {
+    /// The issuer must sign with Ed25519, the only algorithm chains are validated with.
+    pub fn issue<B: CryptoBackend + SignerInfo>(
+        request: CertificateRequest,
+        issuer: impl Into<String>,
+        issuer_backend: &B,
+    ) -> Result<Self, IssueError<B::SignError>> {
+        let algorithm = issuer_backend.algorithm();
+        if algorithm != Algorithm::Ed25519 {
+            return Err(IssueError::UnsupportedAlgorithm(algorithm));
+        }
+        let mut certificate = Certificate {
+            serial: request.serial,
+            subject: request.subject,
+            issuer: issuer.into(),
+            public_key: request.public_key,
+            not_before: request.not_before,
+            not_after: request.not_after,
+            is_ca: request.is_ca,
+            signature: Vec::new(),
+        };
+        let tbs = certificate.tbs_bytes().map_err(IssueError::FieldTooLong)?;
+        certificate.signature = issuer_backend.sign(&tbs).map_err(IssueError::Backend)?;
+        Ok(certificate)
+    }
+
+    /// Root certificate for a trust anchor, issued by its own key.
+    pub fn self_signed<B: CryptoBackend + SignerInfo>(
+        request: CertificateRequest,
+        backend: &B,
+    ) -> Result<Self, IssueError<B::SignError>> {
+        let subject = request.subject.clone();
+        Self::issue(request, subject, backend)
+    }
+
+    /// The signed part: every field except the signature.
+    pub fn tbs_bytes(&self) -> Result<Vec<u8>, FieldTooLong> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&self.serial.to_be_bytes());
+        for (field, name) in [("subject", &self.subject), ("issuer", &self.issuer)] {
+            out.extend_from_slice(&u16_len(field, name.len())?);
+            out.extend_from_slice(name.as_bytes());
+        }
+        out.extend_from_slice(&self.public_key);
+        out.extend_from_slice(&self.not_before.to_be_bytes());
+        out.extend_from_slice(&self.not_after.to_be_bytes());
+        out.push(self.is_ca as u8);
+        Ok(out)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FieldTooLong> {
+        let mut out = self.tbs_bytes()?;
+        out.extend_from_slice(&u16_len("signature", self.signature.len())?);
+        out.extend_from_slice(&self.signature);
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CertError> {
+        Self::decode(bytes).ok_or(CertError::Malformed)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        if reader.take(4).ok()? != MAGIC {
+            return None;
+        }
+        let serial = reader.u64().ok()?;
+        let subject = read_name(&mut reader)?;
+        let issuer = read_name(&mut reader)?;
+        let public_key = reader.take(32).ok()?.try_into().ok()?;
+        let not_before = reader.u64().ok()?;
+        let not_after = reader.u64().ok()?;
+        let is_ca = match reader.u8().ok()? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        let sig_len = reader.u16().ok()? as usize;
+        let signature = reader.take(sig_len).ok()?.to_vec();
+        reader.0.is_empty().then_some(Self {
+            serial,
+            subject,
+            issuer,
+            public_key,
+            not_before,
+            not_after,
+            is_ca,
+            signature,
+        })
+    }
+
+    pub fn is_self_signed(&self) -> bool {
+        self.subject == self.issuer && self.is_signed_by(&self.public_key)
+    }
+
+    pub fn is_signed_by(&self, issuer_public_key: &[u8; 32]) -> bool {
+        // a certificate that can not be encoded was never signed
+        self.tbs_bytes()
+            .is_ok_and(|tbs| verify_ed25519(issuer_public_key, &tbs, &self.signature))
+    }
+
+    /// Verifies a signature made by the certified key, check the chain first.
+    pub fn verify_signature(&self, data: &[u8], sig: &[u8]) -> bool {
+        verify_ed25519(&self.public_key, data, sig)
+    }
+
+    fn check_validity(&self, at: u64) -> Result<(), CertError> {
+        if at < self.not_before {
+            return Err(CertError::NotYetValid {
+                subject: self.subject.clone(),
+                not_before: self.not_before,
+            });
+        }
+        if at >= self.not_after {
+            return Err(CertError::Expired {
+                subject: self.subject.clone(),
+                not_after: self.not_after,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn read_name(reader: &mut Reader) -> Option<String> {
+    let len = reader.u16().ok()? as usize;
+    String::from_utf8(reader.take(len).ok()?.to_vec()).ok()
+}
+
+/// Serials withdrawn by their issuer before expiry.
+#[derive(Debug, Clone, Default)]
+pub struct RevocationList {
+    revoked: HashSet<(String, u64)>,
+}
+
+impl RevocationList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn revoke(&mut self, issuer: impl Into<String>, serial: u64) {
+        self.revoked.insert((issuer.into(), serial));
+    }
+
+    pub fn is_revoked(&self, certificate: &Certificate) -> bool {
+        self.revoked
+            .contains(&(certificate.issuer.clone(), certificate.serial))
+    }
+}
+
+/// Builds the chain from `leaf` up to one of `anchors` and checks every link at time `at`.
+///
+/// Returns the chain leaf first, ending with the trust anchor. Issuers are looked up by
+/// name among `intermediates` and `anchors`, a candidate is only accepted when its key
+/// verifies the signature, so name collisions can not be used to forge a chain.
+pub fn validate_chain(
+    leaf: &Certificate,
+    intermediates: &[Certificate],
+    anchors: &[Certificate],
+    revocations: &RevocationList,
+    at: u64,
+) -> Result<Vec<Certificate>, CertError> {
+    let mut chain = vec![leaf.clone()];
+
+    loop {
+        let current = chain.last().expect("chain starts with the leaf");
+        current.check_validity(at)?;
+        if revocations.is_revoked(current) {
+            return Err(CertError::Revoked {
+                subject: current.subject.clone(),
+                serial: current.serial,
+            });
+        }
+
+        // trust ends at an anchor, which is trusted as configured and needs no issuer
+        if anchors.contains(current) {
+            return Ok(chain);
+        }
+        if chain.len() > MAX_CHAIN_LEN {
+            return Err(CertError::ChainTooLong);
+        }
+
+        let mut signers = anchors
+            .iter()
+            .chain(intermediates)
+            .filter(|c| c.subject == current.issuer && current.is_signed_by(&c.public_key));
+        // several certificates may carry the issuer's name and key, any CA among them will do
+        let issuer = match signers.clone().find(|c| c.is_ca) {
+            Some(issuer) => issuer,
+            None => {
+                return Err(match signers.next() {
+                    Some(signer) => CertError::NotACa {
+                        subject: signer.subject.clone(),
+                    },
+                    None => CertError::UnknownIssuer {
+                        subject: current.subject.clone(),
+                        issuer: current.issuer.clone(),
+                    },
+                });
+            }
+        };
+        chain.push(issuer.clone());
+    }
+}
+
+#[cfg(test)]
+use super::InMemoryKeys;
+
+#[cfg(test)]
+fn request(serial: u64, subject: &str, keys: &InMemoryKeys, is_ca: bool) -> CertificateRequest {
+    CertificateRequest {
+        serial,
+        subject: subject.to_string(),
+        public_key: keys.public_key(),
+        not_before: 1_000,
+        not_after: 2_000,
+        is_ca,
+    }
+}
+
+#[cfg(test)]
+struct Pki {
+    root: Certificate,
+    intermediate: Certificate,
+    leaf: Certificate,
+    leaf_keys: InMemoryKeys,
+}
+
+#[cfg(test)]
+fn pki() -> Pki {
+    let root_keys = InMemoryKeys::from_seed([20; 32]);
+    let ca_keys = InMemoryKeys::from_seed([21; 32]);
+    let leaf_keys = InMemoryKeys::from_seed([22; 32]);
+
+    let root =
+        Certificate::self_signed(request(1, "Root CA", &root_keys, true), &root_keys).unwrap();
+    let intermediate = Certificate::issue(
+        request(2, "Signing CA", &ca_keys, true),
+        "Root CA",
+        &root_keys,
+    )
+    .unwrap();
+    let leaf = Certificate::issue(
+        request(3, "release-bot", &leaf_keys, false),
+        "Signing CA",
+        &ca_keys,
+    )
+    .unwrap();
+    Pki {
+        root,
+        intermediate,
+        leaf,
+        leaf_keys,
+    }
+}
+
+#[test]
+fn test_chain_validates_to_trust_anchor() {
+    let pki = pki();
+    let chain = validate_chain(
+        &pki.leaf,
+        std::slice::from_ref(&pki.intermediate),
+        std::slice::from_ref(&pki.root),
+        &RevocationList::new(),
+        1_500,
+    )
+    .unwrap();
+    let subjects: Vec<&str> = chain.iter().map(|c| c.subject.as_str()).collect();
+    assert_eq!(subjects, vec!["release-bot", "Signing CA", "Root CA"]);
+    assert!(pki.root.is_self_signed());
+
+    // the identity is tied to signatures made with the certified key
+    let Ok(sig) = pki.leaf_keys.sign(b"artifact");
+    assert!(chain[0].verify_signature(b"artifact", &sig));
+    assert_eq!(
+        Certificate::from_bytes(&pki.leaf.to_bytes().unwrap()),
+        Ok(pki.leaf.clone())
+    );
+    assert_eq!(
+        Certificate::from_bytes(&pki.leaf.to_bytes().unwrap()[..50]),
+        Err(CertError::Malformed)
+    );
+}
+
+#[test]
+fn test_chain_validation_errors() {
+    let pki = pki();
+    let intermediates = std::slice::from_ref(&pki.intermediate);
+    let anchors = std::slice::from_ref(&pki.root);
+    let validate = |leaf: &Certificate, intermediates: &[Certificate], crl: &RevocationList, at| {
+        validate_chain(leaf, intermediates, anchors, crl, at)
+    };
+    let no_crl = RevocationList::new();
+
+    assert!(matches!(
+        validate(&pki.leaf, intermediates, &no_crl, 999),
+        Err(CertError::NotYetValid { .. })
+    ));
+    assert_eq!(
+        validate(&pki.leaf, intermediates, &no_crl, 2_000),
+        Err(CertError::Expired {
+            subject: "release-bot".to_string(),
+            not_after: 2_000
+        })
+    );
+    assert_eq!(
+        validate(&pki.leaf, &[], &no_crl, 1_500),
+        Err(CertError::UnknownIssuer {
+            subject: "release-bot".to_string(),
+            issuer: "Signing CA".to_string()
+        })
+    );
+
+    let mut crl = RevocationList::new();
+    crl.revoke("Root CA", 2);
+    assert_eq!(
+        validate(&pki.leaf, intermediates, &crl, 1_500),
+        Err(CertError::Revoked {
+            subject: "Signing CA".to_string(),
+            serial: 2
+        })
+    );
+
+    // modified fields no longer match the issuer signature
+    let mut tampered = pki.leaf.clone();
+    tampered.not_after = 9_000;
+    assert!(matches!(
+        validate(&tampered, intermediates, &no_crl, 1_500),
+        Err(CertError::UnknownIssuer { .. })
+    ));
+
+    // a leaf key can not act as a CA
+    let rogue_keys = InMemoryKeys::from_seed([23; 32]);
+    let rogue = Certificate::issue(
+        request(4, "rogue", &rogue_keys, false),
+        "release-bot",
+        &pki.leaf_keys,
+    )
+    .unwrap();
+    assert_eq!(
+        validate(
+            &rogue,
+            &[pki.leaf.clone(), pki.intermediate.clone()],
+            &no_crl,
+            1_500
+        ),
+        Err(CertError::NotACa {
+            subject: "release-bot".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_issuer_must_sign_with_ed25519() {
+    use super::HmacBackend;
+
+    let leaf_keys = InMemoryKeys::from_seed([24; 32]);
+    let hmac = HmacBackend::new(b"shared secret");
+    assert_eq!(
+        Certificate::issue(request(5, "service", &leaf_keys, false), "HMAC CA", &hmac),
+        Err(IssueError::UnsupportedAlgorithm(Algorithm::HmacSha256))
+    );
+
+    let mut long_name = request(6, "service", &leaf_keys, false);
+    long_name.subject = "s".repeat(70_000);
+    assert!(matches!(
+        Certificate::self_signed(long_name, &leaf_keys),
+        Err(IssueError::FieldTooLong(FieldTooLong {
+            field: "subject",
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_chain_skips_non_ca_namesakes() {
+    let pki = pki();
+    let ca_keys = InMemoryKeys::from_seed([21; 32]);
+    // same name and key as the intermediate, but not allowed to issue
+    let namesake = Certificate::issue(
+        request(7, "Signing CA", &ca_keys, false),
+        "Root CA",
+        &InMemoryKeys::from_seed([20; 32]),
+    )
+    .unwrap();
+
+    let chain = validate_chain(
+        &pki.leaf,
+        &[namesake.clone(), pki.intermediate.clone()],
+        std::slice::from_ref(&pki.root),
+        &RevocationList::new(),
+        1_500,
+    )
+    .unwrap();
+    assert_eq!(chain[1], pki.intermediate);
+
+    assert_eq!(
+        validate_chain(
+            &pki.leaf,
+            &[namesake],
+            std::slice::from_ref(&pki.root),
+            &RevocationList::new(),
+            1_500,
+        ),
+        Err(CertError::NotACa {
+            subject: "Signing CA".to_string()
+        })
+    );
+}
@@ -0,0 +1,450 @@
+use super::CryptoBackend;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 4] = b"NTSE";
+// prefix of the bytes actually signed, so an envelope signature can not be replayed as a raw one
+const DOMAIN: &[u8] = b"never_type signed envelope";
+pub const ENVELOPE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Ed25519,
+    HmacSha256,
+}
+
+impl Algorithm {
+    pub fn id(self) -> u8 {
+        match self {
+            Algorithm::Ed25519 => 1,
+            Algorithm::HmacSha256 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Algorithm::Ed25519),
+            2 => Some(Algorithm::HmacSha256),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Ed25519 => "ed25519",
+            Algorithm::HmacSha256 => "hmac-sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ed25519" => Some(Algorithm::Ed25519),
+            "hmac-sha256" => Some(Algorithm::HmacSha256),
+            _ => None,
+        }
+    }
+}
+
+/// What an envelope records about the key that produced a signature.
+pub trait SignerInfo {
+    fn algorithm(&self) -> Algorithm;
+    fn key_id(&self) -> String;
+}
+
+/// Detached signature over a payload, together with the metadata needed to verify it later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedEnvelope {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub key_id: String,
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    /// SHA-256 of the payload
+    pub payload_digest: [u8; 32],
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownAlgorithm(String),
+    InvalidField(&'static str),
+    TrailingBytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvelopeError<E> {
+    UnsupportedVersion(u8),
+    AlgorithmMismatch {
+        expected: Algorithm,
+        found: Algorithm,
+    },
+    KeyMismatch {
+        expected: String,
+        found: String,
+    },
+    DigestMismatch,
+    BadSignature,
+    FieldTooLong(FieldTooLong),
+    Backend(E),
+}
+
+/// A variable length field does not fit its `u16` length prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldTooLong {
+    pub field: &'static str,
+    pub len: usize,
+}
+
+impl fmt::Display for FieldTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is {} bytes long, at most {} fit",
+            self.field,
+            self.len,
+            u16::MAX
+        )
+    }
+}
+
+impl std::error::Error for FieldTooLong {}
+
+/// big endian `u16` length prefix, refusing lengths that would be truncated
+pub(super) fn u16_len(field: &'static str, len: usize) -> Result<[u8; 2], FieldTooLong> {
+    u16::try_from(len)
+        .map(u16::to_be_bytes)
+        .map_err(|_| FieldTooLong { field, len })
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "envelope is truncated"),
+            DecodeError::BadMagic => write!(f, "not a signed envelope"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported envelope version {v}"),
+            DecodeError::UnknownAlgorithm(alg) => write!(f, "unknown algorithm {alg}"),
+            DecodeError::InvalidField(field) => write!(f, "invalid envelope field `{field}`"),
+            DecodeError::TrailingBytes => write!(f, "unexpected bytes after envelope"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn payload_digest(payload: &[u8]) -> [u8; 32] {
+    Sha256::digest(payload).into()
+}
+
+impl SignedEnvelope {
+    /// Everything except the signature, this is what the backend signs.
+    pub fn signed_bytes(&self) -> Result<Vec<u8>, FieldTooLong> {
+        let mut out = DOMAIN.to_vec();
+        self.write_header(&mut out)?;
+        Ok(out)
+    }
+
+    /// `magic | version | algorithm | key id len (u16) | key id | timestamp (u64) | digest | sig len (u16) | sig`,
+    /// integers are big endian
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FieldTooLong> {
+        let mut out = MAGIC.to_vec();
+        self.write_header(&mut out)?;
+        out.extend_from_slice(&u16_len("signature", self.signature.len())?);
+        out.extend_from_slice(&self.signature);
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader(bytes);
+        if reader.take(4)? != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != ENVELOPE_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let alg = reader.u8()?;
+        let algorithm = Algorithm::from_id(alg)
+            .ok_or_else(|| DecodeError::UnknownAlgorithm(alg.to_string()))?;
+        let key_id_len = reader.u16()? as usize;
+        let key_id = String::from_utf8(reader.take(key_id_len)?.to_vec())
+            .map_err(|_| DecodeError::InvalidField("key_id"))?;
+        let timestamp = reader.u64()?;
+        let payload_digest = reader.take(32)?.try_into().unwrap();
+        let sig_len = reader.u16()? as usize;
+        let signature = reader.take(sig_len)?.to_vec();
+        if !reader.0.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+
+        Ok(Self {
+            version,
+            algorithm,
+            key_id,
+            timestamp,
+            payload_digest,
+            signature,
+        })
+    }
+
+    /// Single line JSON object with base64 encoded binary fields.
+    pub fn to_text(&self) -> String {
+        let text = TextEnvelope {
+            v: self.version,
+            alg: self.algorithm.name().to_string(),
+            kid: self.key_id.clone(),
+            ts: self.timestamp,
+            digest: BASE64.encode(self.payload_digest),
+            sig: BASE64.encode(&self.signature),
+        };
+        serde_json::to_string(&text).expect("envelope always serializes")
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, DecodeError> {
+        let text: TextEnvelope =
+            serde_json::from_str(text).map_err(|_| DecodeError::InvalidField("json"))?;
+        if text.v != ENVELOPE_VERSION {
+            return Err(DecodeError::UnsupportedVersion(text.v));
+        }
+        let algorithm =
+            Algorithm::from_name(&text.alg).ok_or(DecodeError::UnknownAlgorithm(text.alg))?;
+        let payload_digest = BASE64
+            .decode(&text.digest)
+            .ok()
+            .and_then(|d| d.try_into().ok())
+            .ok_or(DecodeError::InvalidField("digest"))?;
+        let signature = BASE64
+            .decode(&text.sig)
+            .map_err(|_| DecodeError::InvalidField("sig"))?;
+
+        Ok(Self {
+            version: text.v,
+            algorithm,
+            key_id: text.kid,
+            timestamp: text.ts,
+            payload_digest,
+            signature,
+        })
+    }
+
+    fn write_header(&self, out: &mut Vec<u8>) -> Result<(), FieldTooLong> {
+        out.push(self.version);
+        out.push(self.algorithm.id());
+        out.extend_from_slice(&u16_len("key_id", self.key_id.len())?);
+        out.extend_from_slice(self.key_id.as_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.payload_digest);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TextEnvelope {
+    v: u8,
+    alg: String,
+    kid: String,
+    ts: u64,
+    digest: String,
+    sig: String,
+}
+
+pub(super) struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.0.len() < n {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Signs the payload digest and metadata, the payload itself is not stored in the envelope.
+pub fn sign_envelope<B: CryptoBackend + SignerInfo>(
+    backend: &B,
+    payload: &[u8],
+) -> Result<SignedEnvelope, EnvelopeError<B::SignError>> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut envelope = SignedEnvelope {
+        version: ENVELOPE_VERSION,
+        algorithm: backend.algorithm(),
+        key_id: backend.key_id(),
+        timestamp,
+        payload_digest: payload_digest(payload),
+        signature: Vec::new(),
+    };
+    let signed = envelope
+        .signed_bytes()
+        .map_err(EnvelopeError::FieldTooLong)?;
+    envelope.signature = backend.sign(&signed).map_err(EnvelopeError::Backend)?;
+    Ok(envelope)
+}
+
+/// Checks that the envelope was made by this backend's key for exactly this payload.
+pub fn verify_envelope<B: CryptoBackend + SignerInfo>(
+    backend: &B,
+    envelope: &SignedEnvelope,
+    payload: &[u8],
+) -> Result<(), EnvelopeError<B::VerifyError>> {
+    if envelope.version != ENVELOPE_VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(envelope.version));
+    }
+    if envelope.algorithm != backend.algorithm() {
+        return Err(EnvelopeError::AlgorithmMismatch {
+            expected: backend.algorithm(),
+            found: envelope.algorithm,
+        });
+    }
+    let key_id = backend.key_id();
+    if envelope.key_id != key_id {
+        return Err(EnvelopeError::KeyMismatch {
+            expected: key_id,
+            found: envelope.key_id.clone(),
+        });
+    }
+    if envelope.payload_digest != payload_digest(payload) {
+        return Err(EnvelopeError::DigestMismatch);
+    }
+    let signed = envelope
+        .signed_bytes()
+        .map_err(EnvelopeError::FieldTooLong)?;
+    match backend.verify(&signed, &envelope.signature) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(EnvelopeError::BadSignature),
+        Err(e) => Err(EnvelopeError::Backend(e)),
+    }
+}
+
+#[cfg(test)]
+use super::InMemoryKeys;
+
+#[test]
+fn test_envelope_round_trips() {
+    let keys = InMemoryKeys::from_seed([9; 32]);
+    let envelope = sign_envelope(&keys, b"quarterly report").unwrap();
+
+    assert_eq!(envelope.algorithm, Algorithm::Ed25519);
+    assert_eq!(envelope.key_id, keys.key_id());
+    assert_eq!(envelope.payload_digest, payload_digest(b"quarterly report"));
+
+    let binary = SignedEnvelope::from_bytes(&envelope.to_bytes().unwrap()).unwrap();
+    let text = SignedEnvelope::from_text(&envelope.to_text()).unwrap();
+    assert_eq!(binary, envelope);
+    assert_eq!(text, envelope);
+    assert_eq!(verify_envelope(&keys, &text, b"quarterly report"), Ok(()));
+}
+
+#[test]
+fn test_envelope_verification_failures() {
+    let keys = InMemoryKeys::from_seed([9; 32]);
+    let other = InMemoryKeys::from_seed([10; 32]);
+    let envelope = sign_envelope(&keys, b"payload").unwrap();
+
+    assert_eq!(
+        verify_envelope(&keys, &envelope, b"payloaD"),
+        Err(EnvelopeError::DigestMismatch)
+    );
+    assert!(matches!(
+        verify_envelope(&other, &envelope, b"payload"),
+        Err(EnvelopeError::KeyMismatch { .. })
+    ));
+
+    // metadata is covered by the signature
+    let mut backdated = envelope.clone();
+    backdated.timestamp -= 3600;
+    assert_eq!(
+        verify_envelope(&keys, &backdated, b"payload"),
+        Err(EnvelopeError::BadSignature)
+    );
+
+    let mut newer = envelope.clone();
+    newer.version = 2;
+    assert_eq!(
+        verify_envelope(&keys, &newer, b"payload"),
+        Err(EnvelopeError::UnsupportedVersion(2))
+    );
+}
+
+#[test]
+fn test_envelope_decode_errors() {
+    let keys = InMemoryKeys::from_seed([9; 32]);
+    let envelope = sign_envelope(&keys, b"payload").unwrap();
+    let bytes = envelope.to_bytes().unwrap();
+
+    assert_eq!(
+        SignedEnvelope::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+        SignedEnvelope::from_bytes(b"JUNKJUNK"),
+        Err(DecodeError::BadMagic)
+    );
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert_eq!(
+        SignedEnvelope::from_bytes(&extra),
+        Err(DecodeError::TrailingBytes)
+    );
+    let mut unknown_alg = bytes.clone();
+    unknown_alg[5] = 0xff;
+    assert_eq!(
+        SignedEnvelope::from_bytes(&unknown_alg),
+        Err(DecodeError::UnknownAlgorithm("255".to_string()))
+    );
+
+    let text = envelope.to_text().replace("\"v\":1", "\"v\":7");
+    assert_eq!(
+        SignedEnvelope::from_text(&text),
+        Err(DecodeError::UnsupportedVersion(7))
+    );
+    assert_eq!(
+        SignedEnvelope::from_text("{not json"),
+        Err(DecodeError::InvalidField("json"))
+    );
+}
+
+#[test]
+fn test_oversized_fields_are_refused() {
+    let keys = InMemoryKeys::from_seed([9; 32]);
+    let mut envelope = sign_envelope(&keys, b"payload").unwrap();
+
+    envelope.signature = vec![0; usize::from(u16::MAX) + 1];
+    assert_eq!(
+        envelope.to_bytes(),
+        Err(FieldTooLong {
+            field: "signature",
+            len: 65536
+        })
+    );
+
+    envelope.key_id = "k".repeat(70_000);
+    assert!(matches!(
+        envelope.signed_bytes(),
+        Err(FieldTooLong {
+            field: "key_id",
+            ..
+        })
+    ));
+}
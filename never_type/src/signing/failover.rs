@@ -0,0 +1,266 @@
+use super::{BatchVerification, CryptoBackend, HsmBackend, HsmError};
+use crate::async_task::Backoff;
+use std::thread;
+
+/// What to do after an HSM call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// transient, try the same device again after a delay
+    Retry,
+    /// the device is gone, move on to the next one without retrying it
+    Failover,
+    /// the request itself is bad (wrong PIN, missing key), no device will accept it
+    Abort,
+}
+
+impl HsmError {
+    pub fn retry_decision(&self) -> RetryDecision {
+        match self {
+            HsmError::Timeout => RetryDecision::Retry,
+            HsmError::DeviceNotFound => RetryDecision::Failover,
+            HsmError::OperationFailed => RetryDecision::Abort,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedAttempt {
+    pub device_id: String,
+    /// counting from 1 on each device
+    pub attempt: u32,
+    pub error: HsmError,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailoverError {
+    /// an error retrying can not fix, `earlier` holds the failures before it
+    Permanent {
+        failure: FailedAttempt,
+        earlier: Vec<FailedAttempt>,
+    },
+    /// every device was tried, in order of the attempts made
+    AllDevicesFailed(Vec<FailedAttempt>),
+}
+
+/// Tries HSM devices in order, retrying timeouts with backoff and failing over when a device is gone.
+#[derive(Debug)]
+pub struct RetryingHsmBackend {
+    devices: Vec<HsmBackend>,
+    backoff: Backoff,
+    attempts_per_device: u32,
+}
+
+impl RetryingHsmBackend {
+    /// all devices must hold the same key, otherwise signatures depend on which one answered;
+    /// panics if `devices` is empty
+    pub fn new(devices: Vec<HsmBackend>) -> Self {
+        assert!(!devices.is_empty(), "at least one HSM device is needed");
+        Self {
+            devices,
+            backoff: Backoff::default(),
+            attempts_per_device: 3,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_attempts_per_device(mut self, attempts: u32) -> Self {
+        self.attempts_per_device = attempts.max(1);
+        self
+    }
+
+    fn run<T>(&self, op: impl Fn(&HsmBackend) -> Result<T, HsmError>) -> Result<T, FailoverError> {
+        let mut history = Vec::new();
+
+        for device in &self.devices {
+            for attempt in 1..=self.attempts_per_device {
+                let error = match op(device) {
+                    Ok(value) => return Ok(value),
+                    Err(error) => error,
+                };
+                let failure = FailedAttempt {
+                    device_id: device.device_id().to_string(),
+                    attempt,
+                    error,
+                };
+                match error.retry_decision() {
+                    RetryDecision::Abort => {
+                        return Err(FailoverError::Permanent {
+                            failure,
+                            earlier: history,
+                        });
+                    }
+                    RetryDecision::Failover => {
+                        history.push(failure);
+                        break;
+                    }
+                    RetryDecision::Retry => {
+                        history.push(failure);
+                        if attempt < self.attempts_per_device {
+                            thread::sleep(self.backoff.delay(attempt));
+                        }
+                    }
+                }
+            }
+        }
+        Err(FailoverError::AllDevicesFailed(history))
+    }
+}
+
+impl CryptoBackend for RetryingHsmBackend {
+    type SignError = FailoverError;
+    type VerifyError = FailoverError;
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, FailoverError> {
+        self.run(|device| device.sign(data))
+    }
+
+    fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, FailoverError> {
+        self.run(|device| device.verify(data, sig))
+    }
+
+    // keeps the single round trip of the device batch, retrying the batch as a whole
+    fn sign_batch(&self, documents: &[&[u8]]) -> Result<Vec<Vec<u8>>, FailoverError> {
+        self.run(|device| device.sign_batch(documents))
+    }
+
+    fn verify_batch(&self, items: &[(&[u8], &[u8])]) -> BatchVerification<FailoverError> {
+        // a failed round trip fails every item with the same error, so the first one stands for all
+        let valid = self.run(|device| {
+            device
+                .verify_batch(items)
+                .results
+                .into_iter()
+                .collect::<Result<Vec<bool>, HsmError>>()
+        });
+        let results = match valid {
+            Ok(valid) => valid.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e); items.len()],
+        };
+        BatchVerification { results }
+    }
+}
+
+#[cfg(test)]
+use super::{Fault, SoftHsm};
+#[cfg(test)]
+use std::time::Duration;
+
+// two devices holding replicas of the same key
+#[cfg(test)]
+fn replicated(hsm: &SoftHsm) -> RetryingHsmBackend {
+    let devices = ["HSM-A", "HSM-B"]
+        .into_iter()
+        .map(|id| {
+            hsm.add_device(id);
+            let slot = hsm.init_slot(id, "1234").unwrap();
+            let key = hsm.generate_key(id, slot, "1234", [15; 32]).unwrap();
+            HsmBackend::connect(hsm, id, slot, "1234", key)
+        })
+        .collect();
+    RetryingHsmBackend::new(devices).with_backoff(Backoff {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(2),
+        multiplier: 2,
+    })
+}
+
+#[test]
+fn test_retries_transient_errors_on_same_device() {
+    let hsm = SoftHsm::new();
+    let backend = replicated(&hsm);
+    hsm.inject_faults("HSM-A", Fault::Timeout, 2).unwrap();
+
+    assert!(backend.sign(b"doc").is_ok());
+    assert_eq!(hsm.round_trips("HSM-A"), Ok(3));
+    assert_eq!(hsm.round_trips("HSM-B"), Ok(0));
+}
+
+#[test]
+fn test_fails_over_to_next_device() {
+    let hsm = SoftHsm::new();
+    let backend = replicated(&hsm);
+
+    hsm.inject_faults("HSM-A", Fault::Timeout, 3).unwrap();
+    let sig = backend.sign(b"doc").unwrap();
+    assert_eq!(hsm.round_trips("HSM-B"), Ok(1));
+
+    // a missing device is skipped without retries
+    hsm.remove_device("HSM-A");
+    assert_eq!(backend.verify(b"doc", &sig), Ok(true));
+    assert_eq!(hsm.round_trips("HSM-B"), Ok(2));
+}
+
+#[test]
+fn test_permanent_errors_and_exhaustion() {
+    let hsm = SoftHsm::new();
+    let backend = replicated(&hsm);
+
+    hsm.inject_faults("HSM-A", Fault::Timeout, 1).unwrap();
+    hsm.inject_faults("HSM-A", Fault::OperationFailed, 1)
+        .unwrap();
+    let Err(FailoverError::Permanent { failure, earlier }) = backend.sign(b"doc") else {
+        panic!("operation failures must not be retried");
+    };
+    assert_eq!(failure.error, HsmError::OperationFailed);
+    assert_eq!(failure.attempt, 2);
+    assert_eq!(earlier.len(), 1);
+    assert_eq!(hsm.round_trips("HSM-B"), Ok(0));
+
+    hsm.remove_device("HSM-A");
+    hsm.inject_faults("HSM-B", Fault::Timeout, 3).unwrap();
+    let Err(FailoverError::AllDevicesFailed(history)) = backend.sign(b"doc") else {
+        panic!("no device can answer");
+    };
+    let summary: Vec<(&str, u32, HsmError)> = history
+        .iter()
+        .map(|f| (f.device_id.as_str(), f.attempt, f.error))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("HSM-A", 1, HsmError::DeviceNotFound),
+            ("HSM-B", 1, HsmError::Timeout),
+            ("HSM-B", 2, HsmError::Timeout),
+            ("HSM-B", 3, HsmError::Timeout),
+        ]
+    );
+}
+
+#[test]
+fn test_verify_batch_retries_the_whole_batch() {
+    let hsm = SoftHsm::new();
+    let backend = replicated(&hsm);
+    let docs: [&[u8]; 3] = [b"a", b"b", b"c"];
+    let sigs = backend.sign_batch(&docs).unwrap();
+    let items: Vec<(&[u8], &[u8])> = vec![
+        (docs[0], &sigs[0]),
+        (docs[1], &sigs[1]),
+        (b"tampered", &sigs[2]),
+    ];
+
+    hsm.inject_faults("HSM-A", Fault::Timeout, 1).unwrap();
+    let batch = backend.verify_batch(&items);
+    assert_eq!(batch.results, vec![Ok(true), Ok(true), Ok(false)]);
+    // one round trip for signing, two for the batch: the timeout and its retry
+    assert_eq!(hsm.round_trips("HSM-A"), Ok(3));
+
+    hsm.remove_device("HSM-A");
+    hsm.remove_device("HSM-B");
+    let batch = backend.verify_batch(&items);
+    assert!(
+        batch
+            .results
+            .iter()
+            .all(|r| matches!(r, Err(FailoverError::AllDevicesFailed(_))))
+    );
+}
+
+#[test]
+#[should_panic(expected = "at least one HSM device is needed")]
+fn test_requires_a_device() {
+    RetryingHsmBackend::new(Vec::new());
+}
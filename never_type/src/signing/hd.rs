@@ -0,0 +1,287 @@
+use super::InMemoryKeys;
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroize;
+
+pub const HARDENED: u32 = 1 << 31;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DerivationError {
+    /// SLIP-0010 seeds are 16 to 64 bytes
+    InvalidSeedLength(usize),
+    /// paths start with `m`
+    MissingMaster,
+    InvalidIndex(String),
+    /// Ed25519 only has hardened derivation
+    NonHardened(u32),
+}
+
+impl fmt::Display for DerivationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DerivationError::InvalidSeedLength(len) => {
+                write!(f, "seed must be 16 to 64 bytes, got {len}")
+            }
+            DerivationError::MissingMaster => write!(f, "derivation path must start with `m`"),
+            DerivationError::InvalidIndex(segment) => {
+                write!(f, "invalid derivation path segment `{segment}`")
+            }
+            DerivationError::NonHardened(index) => {
+                write!(f, "index {index} must be hardened for Ed25519")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DerivationError {}
+
+/// Path like `m/44'/0'/7'`; `'`, `h` and `H` mark hardened indices.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// raw indices, hardened ones with [`HARDENED`] set
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = DerivationError;
+
+    fn from_str(path: &str) -> Result<Self, DerivationError> {
+        let mut segments = path.trim().split('/');
+        if segments.next() != Some("m") {
+            return Err(DerivationError::MissingMaster);
+        }
+
+        let mut indices = Vec::new();
+        for segment in segments {
+            let invalid = || DerivationError::InvalidIndex(segment.to_string());
+            let number = segment.strip_suffix(['\'', 'h', 'H']).ok_or_else(|| {
+                match segment.parse::<u32>() {
+                    Ok(index) if index < HARDENED => DerivationError::NonHardened(index),
+                    _ => invalid(),
+                }
+            })?;
+            // `+5` or ` 5` parse as u32 but are not valid path segments
+            if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let index = number.parse::<u32>().map_err(|_| invalid())?;
+            if index >= HARDENED {
+                return Err(invalid());
+            }
+            indices.push(index | HARDENED);
+        }
+        Ok(Self(indices))
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            write!(f, "/{}'", index & !HARDENED)?;
+        }
+        Ok(())
+    }
+}
+
+/// SLIP-0010 extended private key for the Ed25519 curve.
+pub struct ExtendedKey {
+    private_key: [u8; 32],
+    chain_code: [u8; 32],
+}
+
+impl Drop for ExtendedKey {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+        self.chain_code.zeroize();
+    }
+}
+
+impl ExtendedKey {
+    pub fn master(seed: &[u8]) -> Result<Self, DerivationError> {
+        if !(16..=64).contains(&seed.len()) {
+            return Err(DerivationError::InvalidSeedLength(seed.len()));
+        }
+        Ok(Self::from_hmac(b"ed25519 seed", &[seed]))
+    }
+
+    /// `index` must have [`HARDENED`] set
+    pub fn derive_child(&self, index: u32) -> Result<Self, DerivationError> {
+        if index & HARDENED == 0 {
+            return Err(DerivationError::NonHardened(index));
+        }
+        Ok(Self::from_hmac(
+            &self.chain_code,
+            &[&[0], &self.private_key, &index.to_be_bytes()],
+        ))
+    }
+
+    pub fn derive(&self, path: &DerivationPath) -> Result<Self, DerivationError> {
+        let mut key = Self {
+            private_key: self.private_key,
+            chain_code: self.chain_code,
+        };
+        for &index in path.indices() {
+            key = key.derive_child(index)?;
+        }
+        Ok(key)
+    }
+
+    pub fn chain_code(&self) -> [u8; 32] {
+        self.chain_code
+    }
+
+    pub fn to_keys(&self) -> InMemoryKeys {
+        InMemoryKeys::from_seed(self.private_key)
+    }
+
+    // left half of the HMAC-SHA512 is the key, right half the chain code
+    fn from_hmac(key: &[u8], parts: &[&[u8]]) -> Self {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+        for part in parts {
+            mac.update(part);
+        }
+        let mut digest: [u8; 64] = mac.finalize().into_bytes().into();
+        let mut extended = Self {
+            private_key: [0; 32],
+            chain_code: [0; 32],
+        };
+        extended.private_key.copy_from_slice(&digest[..32]);
+        extended.chain_code.copy_from_slice(&digest[32..]);
+        digest.zeroize();
+        extended
+    }
+}
+
+/// Signing keys for `path` (e.g. `m/0'/42'` for tenant 42) under one master seed.
+pub fn derive_keys(master_seed: &[u8], path: &str) -> Result<InMemoryKeys, DerivationError> {
+    let path: DerivationPath = path.parse()?;
+    Ok(ExtendedKey::master(master_seed)?.derive(&path)?.to_keys())
+}
+
+#[cfg(test)]
+use super::from_hex;
+
+#[test]
+fn test_slip10_ed25519_vector_1() {
+    let seed = from_hex("000102030405060708090a0b0c0d0e0f");
+    // (path, chain code, private key, public key without the 0x00 prefix)
+    let vectors = [
+        (
+            "m",
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb",
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+            "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed",
+        ),
+        (
+            "m/0H",
+            "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69",
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+            "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c",
+        ),
+        (
+            "m/0H/1H",
+            "a320425f77d1b5c2505a6b1b27382b37368ee640e3557c315416801243552f14",
+            "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+            "1932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187",
+        ),
+        (
+            "m/0H/1H/2H",
+            "2e69929e00b5ab250f49c3fb1c12f252de4fed2c1db88387094a0f8c4c9ccd6c",
+            "92a5b23c0b8a99e37d07df3fb9966917f5d06e02ddbd909c7e184371463e9fc9",
+            "ae98736566d30ed0e9d2f4486a64bc95740d89c7db33f52121f8ea8f76ff0fc1",
+        ),
+        (
+            "m/0H/1H/2H/2H",
+            "8f6d87f93d750e0efccda017d662a1b31a266e4a6f5993b15f5c1f07f74dd5cc",
+            "30d1dc7e5fc04c31219ab25a27ae00b50f6fd66622f6e9c913253d6511d1e662",
+            "8abae2d66361c879b900d204ad2cc4984fa2aa344dd7ddc46007329ac76c429c",
+        ),
+        (
+            "m/0H/1H/2H/2H/1000000000H",
+            "68789923a0cac2cd5a29172a475fe9e0fb14cd6adb5ad98a3fa70333e7afa230",
+            "8f94d394a8e8fd6b1bc2f3f49f5c47e385281d5c17e65324b0f62483e37e8793",
+            "3c24da049451555d51a7014a37337aa4e12d41e485abccfa46b47dfb2af54b7a",
+        ),
+    ];
+
+    let master = ExtendedKey::master(&seed).unwrap();
+    for (path, chain_code, private_key, public_key) in vectors {
+        let key = master.derive(&path.parse().unwrap()).unwrap();
+        assert_eq!(key.chain_code().to_vec(), from_hex(chain_code), "{path}");
+        assert_eq!(key.private_key.to_vec(), from_hex(private_key), "{path}");
+        assert_eq!(
+            key.to_keys().public_key().to_vec(),
+            from_hex(public_key),
+            "{path}"
+        );
+        assert_eq!(
+            derive_keys(&seed, path).unwrap().public_key().to_vec(),
+            from_hex(public_key)
+        );
+    }
+}
+
+#[test]
+fn test_slip10_ed25519_vector_2() {
+    let seed = from_hex(
+        "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+    );
+    let master = ExtendedKey::master(&seed).unwrap();
+    assert_eq!(
+        master.chain_code().to_vec(),
+        from_hex("ef70a74db9c3a5af931b5fe73ed8e1a53464133654fd55e7a66f8570b8e33c3b")
+    );
+    assert_eq!(
+        master.private_key.to_vec(),
+        from_hex("171cb88b1b3c1db25add599712e36245d75bc65a1a5c9e18d76f9f2b1eab4012")
+    );
+
+    let child = master.derive(&"m/0'".parse().unwrap()).unwrap();
+    assert_eq!(
+        child.chain_code().to_vec(),
+        from_hex("0b78a3226f915c082bf118f83618a618ab6dec793752624cbeb622acb562862d")
+    );
+    assert_eq!(
+        child.private_key.to_vec(),
+        from_hex("1559eb2bbec5790b0c65d8693e4d0875b1747f4970ae8b650486ed7470845635")
+    );
+}
+
+#[test]
+fn test_derivation_path_parsing() {
+    let path: DerivationPath = "m/44'/0h/7H".parse().unwrap();
+    assert_eq!(path.indices(), &[44 | HARDENED, HARDENED, 7 | HARDENED]);
+    assert_eq!(path.to_string(), "m/44'/0'/7'");
+    assert!("m".parse::<DerivationPath>().unwrap().indices().is_empty());
+
+    let err = |p: &str| p.parse::<DerivationPath>().unwrap_err();
+    assert_eq!(err("44'/0'"), DerivationError::MissingMaster);
+    assert_eq!(err(""), DerivationError::MissingMaster);
+    assert_eq!(err("m/44'/0"), DerivationError::NonHardened(0));
+    assert_eq!(err("m/x'"), DerivationError::InvalidIndex("x'".to_string()));
+    assert_eq!(err("m//1'"), DerivationError::InvalidIndex("".to_string()));
+    assert_eq!(
+        err("m/+1'"),
+        DerivationError::InvalidIndex("+1'".to_string())
+    );
+    assert_eq!(
+        err("m/2147483648'"),
+        DerivationError::InvalidIndex("2147483648'".to_string())
+    );
+
+    assert_eq!(
+        derive_keys(&[0; 8], "m/0'").unwrap_err(),
+        DerivationError::InvalidSeedLength(8)
+    );
+    // different tenants get unrelated keys from the same master seed
+    let tenant_a = derive_keys(&[1; 32], "m/0'/1'").unwrap();
+    let tenant_b = derive_keys(&[1; 32], "m/0'/2'").unwrap();
+    assert_ne!(tenant_a.public_key(), tenant_b.public_key());
+}
@@ -0,0 +1,273 @@
+use super::{CryptoBackend, InMemoryKeys};
+use std::time::SystemTime;
+
+/// Period in which a key may be used for new signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validity {
+    pub not_before: SystemTime,
+    /// `None` for a key that has no planned retirement yet
+    pub not_after: Option<SystemTime>,
+}
+
+impl Validity {
+    pub fn contains(&self, at: SystemTime) -> bool {
+        at >= self.not_before && self.not_after.is_none_or(|end| at < end)
+    }
+}
+
+#[derive(Debug)]
+pub struct KeyEntry<B> {
+    pub key_id: String,
+    pub backend: B,
+    pub validity: Validity,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyedSignature {
+    pub key_id: String,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateKeyId(pub String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyRingError<E> {
+    NoActiveKey { at: SystemTime },
+    Backend { key_id: String, error: E },
+}
+
+/// Several keys with validity windows: signs with the key active now and keeps
+/// verifying signatures made by keys that were since rotated out.
+#[derive(Debug)]
+pub struct KeyRing<B = InMemoryKeys> {
+    entries: Vec<KeyEntry<B>>,
+}
+
+impl<B> Default for KeyRing<B> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<B> KeyRing<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(
+        &mut self,
+        key_id: impl Into<String>,
+        backend: B,
+        validity: Validity,
+    ) -> Result<(), DuplicateKeyId> {
+        let key_id = key_id.into();
+        if self.get(&key_id).is_some() {
+            return Err(DuplicateKeyId(key_id));
+        }
+        self.entries.push(KeyEntry {
+            key_id,
+            backend,
+            validity,
+        });
+        Ok(())
+    }
+
+    /// removes a key completely, its signatures no longer verify
+    pub fn remove(&mut self, key_id: &str) -> Option<KeyEntry<B>> {
+        let index = self.entries.iter().position(|e| e.key_id == key_id)?;
+        Some(self.entries.remove(index))
+    }
+
+    pub fn get(&self, key_id: &str) -> Option<&KeyEntry<B>> {
+        self.entries.iter().find(|e| e.key_id == key_id)
+    }
+
+    /// when windows overlap during a rotation the most recently started key wins
+    pub fn active_key(&self, at: SystemTime) -> Option<&KeyEntry<B>> {
+        self.entries
+            .iter()
+            .filter(|e| e.validity.contains(at))
+            .max_by_key(|e| e.validity.not_before)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &KeyEntry<B>> {
+        self.entries.iter()
+    }
+}
+
+impl<B: CryptoBackend> KeyRing<B> {
+    pub fn sign_at(
+        &self,
+        data: &[u8],
+        at: SystemTime,
+    ) -> Result<KeyedSignature, KeyRingError<B::SignError>> {
+        let entry = self
+            .active_key(at)
+            .ok_or(KeyRingError::NoActiveKey { at })?;
+        let signature = entry
+            .backend
+            .sign(data)
+            .map_err(|error| KeyRingError::Backend {
+                key_id: entry.key_id.clone(),
+                error,
+            })?;
+        Ok(KeyedSignature {
+            key_id: entry.key_id.clone(),
+            signature,
+        })
+    }
+
+    /// Returns the id of the key that produced `sig`, or `None` if no key in the ring did.
+    ///
+    /// The hinted key is tried first, then every other key newest first, so signatures
+    /// with a missing or stale key id still verify. Validity windows only restrict
+    /// signing: retired keys keep verifying what they signed. A key whose backend fails
+    /// is skipped, its error is only returned when no other key verified the signature.
+    pub fn verify_by_id(
+        &self,
+        data: &[u8],
+        sig: &[u8],
+        key_id: Option<&str>,
+    ) -> Result<Option<&str>, B::VerifyError> {
+        let mut candidates: Vec<&KeyEntry<B>> = self.entries.iter().collect();
+        candidates.sort_by_key(|e| {
+            (
+                Some(e.key_id.as_str()) != key_id,
+                std::cmp::Reverse(e.validity.not_before),
+            )
+        });
+
+        let mut last_error = None;
+        for entry in candidates {
+            match entry.backend.verify(data, sig) {
+                Ok(true) => return Ok(Some(&entry.key_id)),
+                Ok(false) => {}
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<B: CryptoBackend> CryptoBackend for KeyRing<B> {
+    type SignError = KeyRingError<B::SignError>;
+    type VerifyError = B::VerifyError;
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Self::SignError> {
+        Ok(self.sign_at(data, SystemTime::now())?.signature)
+    }
+
+    fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, B::VerifyError> {
+        Ok(self.verify_by_id(data, sig, None)?.is_some())
+    }
+}
+
+#[cfg(test)]
+fn quarter(n: u64) -> SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(n * 90 * 24 * 3600)
+}
+
+#[cfg(test)]
+fn rotated_ring() -> KeyRing {
+    let mut ring = KeyRing::new();
+    for (q, seed) in [(1, 1u8), (2, 2), (3, 3)] {
+        let validity = Validity {
+            not_before: quarter(q),
+            not_after: (q < 3).then(|| quarter(q + 1)),
+        };
+        ring.add(
+            format!("Q{q}"),
+            InMemoryKeys::from_seed([seed; 32]),
+            validity,
+        )
+        .unwrap();
+    }
+    ring
+}
+
+#[test]
+fn test_key_ring_signs_with_active_key() {
+    let ring = rotated_ring();
+
+    let Err(KeyRingError::NoActiveKey { .. }) = ring.sign_at(b"doc", quarter(0)) else {
+        panic!("no key is valid before the first quarter");
+    };
+    assert_eq!(ring.sign_at(b"doc", quarter(1)).unwrap().key_id, "Q1");
+    assert_eq!(ring.sign_at(b"doc", quarter(2)).unwrap().key_id, "Q2");
+    assert_eq!(ring.sign_at(b"doc", quarter(30)).unwrap().key_id, "Q3");
+
+    let mut ring = ring;
+    assert_eq!(
+        ring.add(
+            "Q1",
+            InMemoryKeys::from_seed([9; 32]),
+            ring.get("Q1").unwrap().validity
+        ),
+        Err(DuplicateKeyId("Q1".to_string()))
+    );
+}
+
+#[test]
+fn test_key_ring_verifies_retired_keys() {
+    let mut ring = rotated_ring();
+    let old = ring.sign_at(b"doc", quarter(1)).unwrap();
+    let new = ring.sign_at(b"doc", quarter(3)).unwrap();
+
+    let Ok(matched) = ring.verify_by_id(b"doc", &old.signature, Some("Q1"));
+    assert_eq!(matched, Some("Q1"));
+    // wrong or missing hints fall back to the other keys
+    let Ok(matched) = ring.verify_by_id(b"doc", &old.signature, Some("Q3"));
+    assert_eq!(matched, Some("Q1"));
+    let Ok(matched) = ring.verify_by_id(b"doc", &new.signature, None);
+    assert_eq!(matched, Some("Q3"));
+
+    let Ok(matched) = ring.verify_by_id(b"tampered", &old.signature, None);
+    assert_eq!(matched, None);
+
+    ring.remove("Q1");
+    let Ok(valid) = ring.verify(b"doc", &old.signature);
+    assert!(!valid);
+}
+
+#[test]
+fn test_key_ring_skips_failing_backends() {
+    use super::{Fault, HsmBackend, HsmError, SoftHsm};
+
+    let hsm = SoftHsm::new();
+    let mut ring = KeyRing::new();
+    for (id, seed) in [("HSM-A", 1u8), ("HSM-B", 2)] {
+        hsm.add_device(id);
+        let slot = hsm.init_slot(id, "1234").unwrap();
+        let key = hsm.generate_key(id, slot, "1234", [seed; 32]).unwrap();
+        let validity = Validity {
+            not_before: quarter(seed.into()),
+            not_after: None,
+        };
+        ring.add(
+            id,
+            HsmBackend::connect(&hsm, id, slot, "1234", key),
+            validity,
+        )
+        .unwrap();
+    }
+    let old = ring.sign_at(b"doc", quarter(1)).unwrap();
+
+    // the newer key is tried first and fails, the older one still verifies
+    hsm.inject_faults("HSM-B", Fault::Timeout, 1).unwrap();
+    assert_eq!(
+        ring.verify_by_id(b"doc", &old.signature, None),
+        Ok(Some("HSM-A"))
+    );
+
+    hsm.inject_faults("HSM-B", Fault::Timeout, 1).unwrap();
+    assert_eq!(
+        ring.verify_by_id(b"tampered", &old.signature, None),
+        Err(HsmError::Timeout)
+    );
+}
@@ -0,0 +1,351 @@
+use super::InMemoryKeys;
+use super::envelope::Reader;
+use super::hmac::sha256;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, Zeroizing};
+
+const MAGIC: &[u8; 4] = b"NTKS";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+// 32 byte seed + 16 byte Poly1305 tag
+const SEALED_LEN: usize = 48;
+// highest costs a file may ask for, so a crafted header can not make loading exhaust memory or time
+const MAX_KDF_PARAMS: KdfParams = KdfParams {
+    memory_kib: 1024 * 1024,
+    iterations: 64,
+    parallelism: 16,
+};
+
+/// Argon2id cost parameters, stored in the file so they can be raised for new files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    fn within_limits(&self) -> bool {
+        self.memory_kib <= MAX_KDF_PARAMS.memory_kib
+            && self.iterations <= MAX_KDF_PARAMS.iterations
+            && self.parallelism <= MAX_KDF_PARAMS.parallelism
+    }
+}
+
+impl Default for KdfParams {
+    // the OWASP recommended minimum for Argon2id
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyStoreError {
+    Io(io::Error),
+    /// the file is intact but the password does not decrypt it
+    WrongPassword,
+    /// the file is not a key store or was modified
+    Corrupted(&'static str),
+    InvalidKdfParams,
+}
+
+impl fmt::Display for KeyStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyStoreError::Io(e) => write!(f, "key store I/O error: {e}"),
+            KeyStoreError::WrongPassword => write!(f, "wrong key store password"),
+            KeyStoreError::Corrupted(what) => write!(f, "corrupted key store: {what}"),
+            KeyStoreError::InvalidKdfParams => write!(f, "invalid key derivation parameters"),
+        }
+    }
+}
+
+impl std::error::Error for KeyStoreError {}
+
+impl From<io::Error> for KeyStoreError {
+    fn from(e: io::Error) -> Self {
+        KeyStoreError::Io(e)
+    }
+}
+
+/// Password protected file holding one signing key.
+///
+/// Layout: `magic | version | memory_kib | iterations | parallelism | salt | nonce | sealed seed | checksum`.
+/// The header is authenticated as AEAD associated data; the trailing SHA-256 of everything
+/// before it tells a damaged file apart from a wrong password.
+#[derive(Debug, Clone)]
+pub struct KeyStore {
+    path: PathBuf,
+    params: KdfParams,
+}
+
+impl KeyStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            params: KdfParams::default(),
+        }
+    }
+
+    /// only affects saving, loading uses the parameters recorded in the file
+    pub fn with_kdf_params(mut self, params: KdfParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Written to a temporary file first and renamed, so a crash never leaves half a key store.
+    pub fn save(&self, keys: &InMemoryKeys, password: &str) -> Result<(), KeyStoreError> {
+        let bytes = seal_key(keys, password, self.params)?;
+        let tmp = self.tmp_path();
+        write_private(&tmp, &bytes)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    // appended rather than replacing the extension, so `keys.json` and `keys.bin` do not share one
+    fn tmp_path(&self) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".tmp");
+        name.into()
+    }
+
+    pub fn load(&self, password: &str) -> Result<InMemoryKeys, KeyStoreError> {
+        unseal_key(&fs::read(&self.path)?, password)
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    fs::write(path, bytes)
+}
+
+pub fn seal_key(
+    keys: &InMemoryKeys,
+    password: &str,
+    params: KdfParams,
+) -> Result<Vec<u8>, KeyStoreError> {
+    // a file that could not be loaded again must not be written
+    if !params.within_limits() {
+        return Err(KeyStoreError::InvalidKdfParams);
+    }
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::fill(&mut salt).map_err(|e| KeyStoreError::Io(e.into()))?;
+    getrandom::fill(&mut nonce).map_err(|e| KeyStoreError::Io(e.into()))?;
+
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    for value in [params.memory_kib, params.iterations, params.parallelism] {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+
+    let cipher = cipher(password, &salt, params)?;
+    let sealed = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &keys.private_key,
+                aad: &out,
+            },
+        )
+        .expect("encrypting 32 bytes can not fail");
+    out.extend_from_slice(&sealed);
+    let checksum = sha256(&out);
+    out.extend_from_slice(&checksum);
+    Ok(out)
+}
+
+pub fn unseal_key(bytes: &[u8], password: &str) -> Result<InMemoryKeys, KeyStoreError> {
+    let (body, checksum) = bytes
+        .split_last_chunk::<32>()
+        .ok_or(KeyStoreError::Corrupted("file too short"))?;
+    if sha256(body) != *checksum {
+        return Err(KeyStoreError::Corrupted("checksum mismatch"));
+    }
+
+    let truncated = |_| KeyStoreError::Corrupted("file too short");
+    let mut reader = Reader(body);
+    if reader.take(4).map_err(truncated)? != MAGIC {
+        return Err(KeyStoreError::Corrupted("not a key store"));
+    }
+    if reader.u8().map_err(truncated)? != VERSION {
+        return Err(KeyStoreError::Corrupted("unsupported version"));
+    }
+    let mut u32 = || -> Result<u32, KeyStoreError> {
+        let bytes = reader.take(4).map_err(truncated)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let params = KdfParams {
+        memory_kib: u32()?,
+        iterations: u32()?,
+        parallelism: u32()?,
+    };
+    if !params.within_limits() {
+        return Err(KeyStoreError::Corrupted("key derivation cost too high"));
+    }
+    let salt = reader.take(SALT_LEN).map_err(truncated)?;
+    let nonce = reader.take(NONCE_LEN).map_err(truncated)?;
+    let header = &body[..body.len() - reader.0.len()];
+    if reader.0.len() != SEALED_LEN {
+        return Err(KeyStoreError::Corrupted("unexpected key length"));
+    }
+
+    let cipher = cipher(password, salt, params)?;
+    let seed = cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: reader.0,
+                aad: header,
+            },
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| KeyStoreError::WrongPassword)?;
+    let mut private_key = [0u8; 32];
+    private_key.copy_from_slice(&seed);
+    let keys = InMemoryKeys::from_seed(private_key);
+    private_key.zeroize();
+    Ok(keys)
+}
+
+fn cipher(
+    password: &str,
+    salt: &[u8],
+    params: KdfParams,
+) -> Result<XChaCha20Poly1305, KeyStoreError> {
+    let params = argon2::Params::new(
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        Some(32),
+    )
+    .map_err(|_| KeyStoreError::InvalidKdfParams)?;
+    let argon = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(|_| KeyStoreError::InvalidKdfParams)?;
+    Ok(XChaCha20Poly1305::new(key.as_ref().into()))
+}
+
+// cheap parameters so the tests do not spend seconds in the KDF
+#[cfg(test)]
+const TEST_PARAMS: KdfParams = KdfParams {
+    memory_kib: 64,
+    iterations: 1,
+    parallelism: 1,
+};
+
+#[test]
+fn test_key_store_round_trip() {
+    let dir = std::env::temp_dir().join(format!("never_type_key_store_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let store = KeyStore::new(dir.join("signing.key")).with_kdf_params(TEST_PARAMS);
+    let keys = InMemoryKeys::from_seed([11; 32]);
+
+    store.save(&keys, "correct horse").unwrap();
+    let loaded = store.load("correct horse").unwrap();
+    assert_eq!(loaded.public_key(), keys.public_key());
+
+    assert!(matches!(
+        store.load("battery staple"),
+        Err(KeyStoreError::WrongPassword)
+    ));
+    assert!(matches!(
+        KeyStore::new(dir.join("missing.key")).load("correct horse"),
+        Err(KeyStoreError::Io(_))
+    ));
+
+    // stores side by side each get their own temporary file
+    let json = KeyStore::new(dir.join("keys.json"));
+    let bin = KeyStore::new(dir.join("keys.bin"));
+    assert_eq!(json.tmp_path(), dir.join("keys.json.tmp"));
+    assert_ne!(json.tmp_path(), bin.tmp_path());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_key_store_detects_corruption() {
+    let keys = InMemoryKeys::from_seed([12; 32]);
+    let sealed = seal_key(&keys, "pw", TEST_PARAMS).unwrap();
+    // fresh salt and nonce every time
+    assert_ne!(sealed, seal_key(&keys, "pw", TEST_PARAMS).unwrap());
+
+    let mut flipped = sealed.clone();
+    flipped[40] ^= 0x01;
+    assert!(matches!(
+        unseal_key(&flipped, "pw"),
+        Err(KeyStoreError::Corrupted("checksum mismatch"))
+    ));
+    assert!(matches!(
+        unseal_key(&sealed[..20], "pw"),
+        Err(KeyStoreError::Corrupted(_))
+    ));
+
+    // a recomputed checksum does not help when the authenticated header was changed
+    let mut tampered = sealed[..sealed.len() - 32].to_vec();
+    // iterations live at bytes 9..13
+    tampered[9..13].copy_from_slice(&2u32.to_be_bytes());
+    let checksum = sha256(&tampered);
+    tampered.extend_from_slice(&checksum);
+    assert!(matches!(
+        unseal_key(&tampered, "pw"),
+        Err(KeyStoreError::WrongPassword)
+    ));
+}
+
+#[test]
+fn test_key_store_caps_kdf_cost() {
+    let keys = InMemoryKeys::from_seed([13; 32]);
+    let huge = KdfParams {
+        memory_kib: u32::MAX,
+        ..TEST_PARAMS
+    };
+    assert!(matches!(
+        seal_key(&keys, "pw", huge),
+        Err(KeyStoreError::InvalidKdfParams)
+    ));
+
+    // memory_kib lives at bytes 5..9, rejected before Argon2 gets to allocate it
+    let sealed = seal_key(&keys, "pw", TEST_PARAMS).unwrap();
+    let mut tampered = sealed[..sealed.len() - 32].to_vec();
+    tampered[5..9].copy_from_slice(&u32::MAX.to_be_bytes());
+    let checksum = sha256(&tampered);
+    tampered.extend_from_slice(&checksum);
+    assert!(matches!(
+        unseal_key(&tampered, "pw"),
+        Err(KeyStoreError::Corrupted("key derivation cost too high"))
+    ));
+}
@@ -0,0 +1,425 @@
+use super::envelope::{FieldTooLong, Reader, u16_len};
+use super::{CryptoBackend, SignerInfo};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Why a single signer did not contribute a valid signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureReason<E> {
+    Backend(E),
+    InvalidSignature,
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignerFailure<E> {
+    pub index: usize,
+    pub signer_id: String,
+    pub reason: FailureReason<E>,
+}
+
+/// Fewer than `required` signers succeeded, `failures` says which ones did not and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThresholdError<E> {
+    pub required: usize,
+    pub succeeded: Vec<String>,
+    pub failures: Vec<SignerFailure<E>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiSigSignError<E> {
+    Threshold(ThresholdError<E>),
+    FieldTooLong(FieldTooLong),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiSigVerifyError<E> {
+    MalformedBundle,
+    Threshold(ThresholdError<E>),
+}
+
+/// Threshold was met; failures of the remaining signers are still reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Approval<E> {
+    pub approved_by: Vec<String>,
+    pub failures: Vec<SignerFailure<E>>,
+}
+
+/// Why [`MultiSigBackend::new`] refused its signers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiSigConfigError {
+    ZeroThreshold,
+    /// fewer signers than the threshold, so it could never be met
+    TooFewSigners {
+        threshold: usize,
+        signers: usize,
+    },
+    /// the named signer holds the same key as an earlier one
+    DuplicateSigner(String),
+}
+
+impl fmt::Display for MultiSigConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiSigConfigError::ZeroThreshold => write!(f, "threshold must be at least 1"),
+            MultiSigConfigError::TooFewSigners { threshold, signers } => {
+                write!(
+                    f,
+                    "threshold of {threshold} needs more than {signers} signers"
+                )
+            }
+            MultiSigConfigError::DuplicateSigner(name) => {
+                write!(f, "signer {name} uses the key of another signer")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MultiSigConfigError {}
+
+/// Signatures of several signers, each tagged with the signer's index.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SignatureBundle {
+    pub signatures: Vec<(usize, Vec<u8>)>,
+}
+
+impl SignatureBundle {
+    /// `count (u16) | { signer index (u16) | sig len (u16) | sig }*`, big endian
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FieldTooLong> {
+        let mut out = u16_len("signature count", self.signatures.len())?.to_vec();
+        for (index, sig) in &self.signatures {
+            out.extend_from_slice(&u16_len("signer index", *index)?);
+            out.extend_from_slice(&u16_len("signature", sig.len())?);
+            out.extend_from_slice(sig);
+        }
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let count = reader.u16().ok()?;
+        let mut signatures = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let index = reader.u16().ok()? as usize;
+            let len = reader.u16().ok()? as usize;
+            signatures.push((index, reader.take(len).ok()?.to_vec()));
+        }
+        reader.0.is_empty().then_some(Self { signatures })
+    }
+}
+
+/// M-of-N backend: signing asks every signer, verifying needs `threshold` distinct valid signers.
+///
+/// Signers are told apart by [`SignerInfo::key_id`], so one key can not be registered
+/// under several names to meet the threshold on its own.
+#[derive(Debug)]
+pub struct MultiSigBackend<B> {
+    signers: Vec<(String, B)>,
+    threshold: usize,
+}
+
+impl<B: CryptoBackend> MultiSigBackend<B> {
+    pub fn new(signers: Vec<(String, B)>, threshold: usize) -> Result<Self, MultiSigConfigError>
+    where
+        B: SignerInfo,
+    {
+        if threshold == 0 {
+            return Err(MultiSigConfigError::ZeroThreshold);
+        }
+        if threshold > signers.len() {
+            return Err(MultiSigConfigError::TooFewSigners {
+                threshold,
+                signers: signers.len(),
+            });
+        }
+        let mut key_ids = HashSet::new();
+        if let Some((name, _)) = signers
+            .iter()
+            .find(|(_, backend)| !key_ids.insert(backend.key_id()))
+        {
+            return Err(MultiSigConfigError::DuplicateSigner(name.clone()));
+        }
+        Ok(Self { signers, threshold })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn sign_bundle(
+        &self,
+        data: &[u8],
+    ) -> Result<SignatureBundle, ThresholdError<B::SignError>> {
+        let mut bundle = SignatureBundle::default();
+        let mut succeeded = Vec::new();
+        let mut failures = Vec::new();
+
+        for (index, (signer_id, backend)) in self.signers.iter().enumerate() {
+            match backend.sign(data) {
+                Ok(sig) => {
+                    bundle.signatures.push((index, sig));
+                    succeeded.push(signer_id.clone());
+                }
+                Err(e) => failures.push(SignerFailure {
+                    index,
+                    signer_id: signer_id.clone(),
+                    reason: FailureReason::Backend(e),
+                }),
+            }
+        }
+
+        if succeeded.len() >= self.threshold {
+            Ok(bundle)
+        } else {
+            Err(ThresholdError {
+                required: self.threshold,
+                succeeded,
+                failures,
+            })
+        }
+    }
+
+    /// A bundle listing the same signer more than once is malformed.
+    pub fn verify_bundle(
+        &self,
+        data: &[u8],
+        bundle: &SignatureBundle,
+    ) -> Result<Approval<B::VerifyError>, MultiSigVerifyError<B::VerifyError>> {
+        let mut reasons: Vec<Option<FailureReason<B::VerifyError>>> = (0..self.signers.len())
+            .map(|_| Some(FailureReason::Missing))
+            .collect();
+
+        for (index, sig) in &bundle.signatures {
+            let (_, backend) = self
+                .signers
+                .get(*index)
+                .ok_or(MultiSigVerifyError::MalformedBundle)?;
+            if !matches!(reasons[*index], Some(FailureReason::Missing)) {
+                return Err(MultiSigVerifyError::MalformedBundle);
+            }
+            reasons[*index] = match backend.verify(data, sig) {
+                Ok(true) => None,
+                Ok(false) => Some(FailureReason::InvalidSignature),
+                Err(e) => Some(FailureReason::Backend(e)),
+            };
+        }
+
+        let mut approved_by = Vec::new();
+        let mut failures = Vec::new();
+        for (index, reason) in reasons.into_iter().enumerate() {
+            let signer_id = self.signers[index].0.clone();
+            match reason {
+                None => approved_by.push(signer_id),
+                Some(reason) => failures.push(SignerFailure {
+                    index,
+                    signer_id,
+                    reason,
+                }),
+            }
+        }
+
+        if approved_by.len() >= self.threshold {
+            Ok(Approval {
+                approved_by,
+                failures,
+            })
+        } else {
+            Err(MultiSigVerifyError::Threshold(ThresholdError {
+                required: self.threshold,
+                succeeded: approved_by,
+                failures,
+            }))
+        }
+    }
+}
+
+impl<B: CryptoBackend> CryptoBackend for MultiSigBackend<B> {
+    type SignError = MultiSigSignError<B::SignError>;
+    type VerifyError = MultiSigVerifyError<B::VerifyError>;
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, Self::SignError> {
+        self.sign_bundle(data)
+            .map_err(MultiSigSignError::Threshold)?
+            .to_bytes()
+            .map_err(MultiSigSignError::FieldTooLong)
+    }
+
+    /// An unmet threshold is only an error when a backend failed, otherwise the bundle is just not valid.
+    fn verify(&self, data: &[u8], sig: &[u8]) -> Result<bool, Self::VerifyError> {
+        let Some(bundle) = SignatureBundle::from_bytes(sig) else {
+            return Ok(false);
+        };
+        match self.verify_bundle(data, &bundle) {
+            Ok(_) => Ok(true),
+            Err(MultiSigVerifyError::Threshold(e))
+                if e.failures
+                    .iter()
+                    .any(|f| matches!(f.reason, FailureReason::Backend(_))) =>
+            {
+                Err(MultiSigVerifyError::Threshold(e))
+            }
+            Err(_) => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+use super::{HsmBackend, HsmError, InMemoryKeys, SoftHsm};
+
+#[cfg(test)]
+fn approvers(hsm: &SoftHsm) -> MultiSigBackend<HsmBackend> {
+    let signers = ["alice", "bob", "carol"]
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            hsm.add_device(name);
+            let slot = hsm.init_slot(name, "1234").unwrap();
+            let key = hsm.generate_key(name, slot, "1234", [i as u8; 32]).unwrap();
+            (
+                name.to_string(),
+                HsmBackend::connect(hsm, name, slot, "1234", key),
+            )
+        })
+        .collect();
+    MultiSigBackend::new(signers, 2).unwrap()
+}
+
+#[test]
+fn test_multisig_two_of_three() {
+    let hsm = SoftHsm::new();
+    let multisig = approvers(&hsm);
+
+    let sig = multisig.sign(b"release-1.2.tar.gz").unwrap();
+    assert_eq!(multisig.verify(b"release-1.2.tar.gz", &sig), Ok(true));
+    assert_eq!(multisig.verify(b"release-1.3.tar.gz", &sig), Ok(false));
+    assert_eq!(
+        multisig.verify(b"release-1.2.tar.gz", b"garbage"),
+        Ok(false)
+    );
+
+    // one signer down still meets the threshold and the failure is reported
+    hsm.remove_device("carol");
+    let bundle = multisig.sign_bundle(b"release-1.2.tar.gz").unwrap();
+    let approval = multisig
+        .verify_bundle(b"release-1.2.tar.gz", &bundle)
+        .unwrap();
+    assert_eq!(approval.approved_by, vec!["alice", "bob"]);
+    assert_eq!(approval.failures[0].reason, FailureReason::Missing);
+
+    let full = SignatureBundle::from_bytes(&sig).unwrap();
+    let approval = multisig
+        .verify_bundle(b"release-1.2.tar.gz", &full)
+        .unwrap();
+    assert_eq!(
+        approval.failures,
+        vec![SignerFailure {
+            index: 2,
+            signer_id: "carol".to_string(),
+            reason: FailureReason::Backend(HsmError::DeviceNotFound),
+        }]
+    );
+
+    hsm.remove_device("bob");
+    let Err(MultiSigSignError::Threshold(err)) = multisig.sign(b"release-1.2.tar.gz") else {
+        panic!("one signer can not meet a threshold of two");
+    };
+    assert_eq!(err.required, 2);
+    assert_eq!(err.succeeded, vec!["alice"]);
+    assert_eq!(
+        err.failures
+            .iter()
+            .map(|f| f.signer_id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["bob", "carol"]
+    );
+}
+
+#[test]
+fn test_multisig_counts_distinct_signers() {
+    let signers = (0..3)
+        .map(|i| (format!("signer-{i}"), InMemoryKeys::from_seed([i; 32])))
+        .collect();
+    let multisig = MultiSigBackend::new(signers, 2).unwrap();
+    let Ok(alice) = InMemoryKeys::from_seed([0; 32]).sign(b"doc");
+    let Ok(forged) = InMemoryKeys::from_seed([42; 32]).sign(b"doc");
+
+    let forged = SignatureBundle {
+        signatures: vec![(0, alice), (1, forged)],
+    };
+    let Err(MultiSigVerifyError::Threshold(err)) = multisig.verify_bundle(b"doc", &forged) else {
+        panic!("a forged signature must not count");
+    };
+    assert_eq!(err.failures[0].reason, FailureReason::InvalidSignature);
+    assert_eq!(err.failures[1].reason, FailureReason::Missing);
+
+    let out_of_range = SignatureBundle {
+        signatures: vec![(7, vec![0; 64])],
+    };
+    assert_eq!(
+        multisig.verify_bundle(b"doc", &out_of_range),
+        Err(MultiSigVerifyError::MalformedBundle)
+    );
+    assert_eq!(
+        MultiSigBackend::<InMemoryKeys>::new(vec![], 1).unwrap_err(),
+        MultiSigConfigError::TooFewSigners {
+            threshold: 1,
+            signers: 0
+        }
+    );
+    assert_eq!(
+        MultiSigBackend::<InMemoryKeys>::new(vec![], 0).unwrap_err(),
+        MultiSigConfigError::ZeroThreshold
+    );
+}
+
+#[test]
+fn test_multisig_rejects_duplicate_signers() {
+    let k = 3;
+    let signers = (0..k)
+        .map(|i| (format!("alias-{i}"), InMemoryKeys::from_seed([7; 32])))
+        .collect();
+    assert_eq!(
+        MultiSigBackend::new(signers, k).unwrap_err(),
+        MultiSigConfigError::DuplicateSigner("alias-1".to_string())
+    );
+
+    let mut signers: Vec<_> = (0..k)
+        .map(|i| {
+            (
+                format!("signer-{i}"),
+                InMemoryKeys::from_seed([i as u8; 32]),
+            )
+        })
+        .collect();
+    signers.push((
+        "signer-0-again".to_string(),
+        InMemoryKeys::from_seed([0; 32]),
+    ));
+    assert_eq!(
+        MultiSigBackend::new(signers, 2).unwrap_err(),
+        MultiSigConfigError::DuplicateSigner("signer-0-again".to_string())
+    );
+
+    let signers = (0..k)
+        .map(|i| {
+            (
+                format!("signer-{i}"),
+                InMemoryKeys::from_seed([i as u8; 32]),
+            )
+        })
+        .collect();
+    let multisig = MultiSigBackend::new(signers, 2).unwrap();
+    // the same signer k times is not k approvals
+    let Ok(sig) = InMemoryKeys::from_seed([0; 32]).sign(b"doc");
+    let replayed = SignatureBundle {
+        signatures: vec![(0, sig); k],
+    };
+    assert_eq!(
+        multisig.verify_bundle(b"doc", &replayed),
+        Err(MultiSigVerifyError::MalformedBundle)
+    );
+    assert_eq!(
+        multisig.verify(b"doc", &replayed.to_bytes().unwrap()),
+        Ok(false)
+    );
+}
@@ -0,0 +1,232 @@
+use super::{CryptoBackend, HsmError, InMemoryKeys};
+use crate::infallible::InfallibleResultExt;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SlotId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyHandle(pub u32);
+
+/// Failure the simulator can be told to produce on upcoming operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    OperationFailed,
+    Timeout,
+}
+
+/// In-process stand-in for a set of HSM devices, addressed by device id.
+///
+/// Cloning gives another handle to the same simulated devices, so tests can keep
+/// one to unplug devices, inject faults or change latency while backends use it.
+#[derive(Debug, Clone, Default)]
+pub struct SoftHsm {
+    devices: Arc<Mutex<HashMap<String, Device>>>,
+}
+
+#[derive(Debug, Default)]
+struct Device {
+    slots: Vec<Slot>,
+    latency: Duration,
+    faults: VecDeque<Fault>,
+    round_trips: u64,
+}
+
+#[derive(Debug)]
+struct Slot {
+    pin: String,
+    keys: HashMap<KeyHandle, InMemoryKeys>,
+    next_handle: u32,
+}
+
+impl SoftHsm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// plugs in an empty device, replacing any device with the same id
+    pub fn add_device(&self, device_id: impl Into<String>) {
+        self.devices().insert(device_id.into(), Device::default());
+    }
+
+    pub fn remove_device(&self, device_id: &str) -> bool {
+        self.devices().remove(device_id).is_some()
+    }
+
+    pub fn init_slot(&self, device_id: &str, pin: impl Into<String>) -> Result<SlotId, HsmError> {
+        let mut devices = self.devices();
+        let device = devices.get_mut(device_id).ok_or(HsmError::DeviceNotFound)?;
+        device.slots.push(Slot {
+            pin: pin.into(),
+            keys: HashMap::new(),
+            next_handle: 1,
+        });
+        Ok(SlotId(device.slots.len() - 1))
+    }
+
+    /// creates an Ed25519 key inside the slot, the seed never leaves the device again
+    pub fn generate_key(
+        &self,
+        device_id: &str,
+        slot: SlotId,
+        pin: &str,
+        seed: [u8; 32],
+    ) -> Result<KeyHandle, HsmError> {
+        let mut devices = self.devices();
+        let device = devices.get_mut(device_id).ok_or(HsmError::DeviceNotFound)?;
+        let slot = device.login(slot, pin)?;
+        let handle = KeyHandle(slot.next_handle);
+        slot.next_handle += 1;
+        slot.keys.insert(handle, InMemoryKeys::from_seed(seed));
+        Ok(handle)
+    }
+
+    pub fn public_key(
+        &self,
+        device_id: &str,
+        slot: SlotId,
+        key: KeyHandle,
+    ) -> Result<[u8; 32], HsmError> {
+        let devices = self.devices();
+        let device = devices.get(device_id).ok_or(HsmError::DeviceNotFound)?;
+        let slot = device.slots.get(slot.0).ok_or(HsmError::OperationFailed)?;
+        let keys = slot.keys.get(&key).ok_or(HsmError::OperationFailed)?;
+        Ok(keys.public_key())
+    }
+
+    /// every following operation on the device is delayed by `latency`
+    pub fn set_latency(&self, device_id: &str, latency: Duration) -> Result<(), HsmError> {
+        let mut devices = self.devices();
+        let device = devices.get_mut(device_id).ok_or(HsmError::DeviceNotFound)?;
+        device.latency = latency;
+        Ok(())
+    }
+
+    /// the next `count` operations on the device fail with `fault`
+    pub fn inject_faults(
+        &self,
+        device_id: &str,
+        fault: Fault,
+        count: usize,
+    ) -> Result<(), HsmError> {
+        let mut devices = self.devices();
+        let device = devices.get_mut(device_id).ok_or(HsmError::DeviceNotFound)?;
+        device.faults.extend(std::iter::repeat_n(fault, count));
+        Ok(())
+    }
+
+    pub(super) fn sign(&self, session: &Session<'_>, data: &[u8]) -> Result<Vec<u8>, HsmError> {
+        self.run(session, |keys| keys.sign(data).unwrap_infallible())
+    }
+
+    pub(super) fn verify(
+        &self,
+        session: &Session<'_>,
+        data: &[u8],
+        sig: &[u8],
+    ) -> Result<bool, HsmError> {
+        self.run(session, |keys| keys.verify(data, sig).unwrap_infallible())
+    }
+
+    /// the whole batch is a single round trip, it succeeds or fails as a unit
+    pub(super) fn sign_batch(
+        &self,
+        session: &Session<'_>,
+        documents: &[&[u8]],
+    ) -> Result<Vec<Vec<u8>>, HsmError> {
+        self.run(session, |keys| {
+            keys.sign_batch(documents).unwrap_infallible()
+        })
+    }
+
+    pub(super) fn verify_batch(
+        &self,
+        session: &Session<'_>,
+        items: &[(&[u8], &[u8])],
+    ) -> Result<Vec<bool>, HsmError> {
+        self.run(session, |keys| {
+            items
+                .iter()
+                .map(|(data, sig)| keys.verify(data, sig).unwrap_infallible())
+                .collect()
+        })
+    }
+
+    /// number of requests that reached the device, including failed ones
+    pub fn round_trips(&self, device_id: &str) -> Result<u64, HsmError> {
+        let devices = self.devices();
+        let device = devices.get(device_id).ok_or(HsmError::DeviceNotFound)?;
+        Ok(device.round_trips)
+    }
+
+    // one round trip to the device: latency, injected faults, login and key lookup
+    fn run<T>(
+        &self,
+        session: &Session<'_>,
+        op: impl FnOnce(&InMemoryKeys) -> T,
+    ) -> Result<T, HsmError> {
+        let latency = {
+            let devices = self.devices();
+            let device = devices
+                .get(session.device_id)
+                .ok_or(HsmError::DeviceNotFound)?;
+            device.latency
+        };
+        // the lock is not held while "waiting for the device"
+        if latency > session.timeout {
+            thread::sleep(session.timeout);
+            return Err(HsmError::Timeout);
+        }
+        thread::sleep(latency);
+
+        let mut devices = self.devices();
+        // the device might have been unplugged while the request was in flight
+        let device = devices
+            .get_mut(session.device_id)
+            .ok_or(HsmError::DeviceNotFound)?;
+        device.round_trips += 1;
+        match device.faults.pop_front() {
+            Some(Fault::OperationFailed) => return Err(HsmError::OperationFailed),
+            Some(Fault::Timeout) => return Err(HsmError::Timeout),
+            None => {}
+        }
+        let slot = device.login(session.slot, session.pin)?;
+        let keys = slot
+            .keys
+            .get(&session.key)
+            .ok_or(HsmError::OperationFailed)?;
+        Ok(op(keys))
+    }
+
+    fn devices(&self) -> MutexGuard<'_, HashMap<String, Device>> {
+        // a panicking test thread must not take the simulator down with it
+        self.devices
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Device {
+    fn login(&mut self, slot: SlotId, pin: &str) -> Result<&mut Slot, HsmError> {
+        let slot = self
+            .slots
+            .get_mut(slot.0)
+            .ok_or(HsmError::OperationFailed)?;
+        if slot.pin != pin {
+            return Err(HsmError::OperationFailed);
+        }
+        Ok(slot)
+    }
+}
+
+/// Everything the simulator needs to authorize one operation.
+pub(super) struct Session<'a> {
+    pub device_id: &'a str,
+    pub slot: SlotId,
+    pub pin: &'a str,
+    pub key: KeyHandle,
+    pub timeout: Duration,
+}